@@ -0,0 +1,116 @@
+//
+// Parse the below-the-line preferences CSV file, for Senate elections before the 2016 reforms.
+// Each paper is given over several rows, one for each preference.
+// Example file: http://results.aec.gov.au/17496/Website/Downloads/SenateStateBTLPreferences-17496-WA.zip
+//
+
+use aec::data::read_download;
+use defs::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Debug, Deserialize)]
+struct AECBTLPreferenceRow {
+    #[serde(rename = "CandidateId")]
+    candidate_id: u32,
+    #[serde(rename = "Preference")]
+    preference: u32,
+    #[serde(rename = "Batch")]
+    batch: u32,
+    #[serde(rename = "Paper")]
+    paper: u32,
+}
+
+fn process_fd(
+    fd: impl Read,
+    candidate_ids: &HashMap<u32, CandidateIndex>,
+) -> Result<Vec<BallotState>, Box<dyn Error>> {
+    let rows: Vec<AECBTLPreferenceRow> = read_download(fd, "CandidateId")?;
+    let mut papers: HashMap<(u32, u32), Vec<(u32, CandidateIndex)>> = HashMap::new();
+    for row in rows {
+        let candidate = match candidate_ids.get(&row.candidate_id) {
+            Some(candidate) => *candidate,
+            None => return Err(format!("unknown candidate id: {}", row.candidate_id).into()),
+        };
+        papers
+            .entry((row.batch, row.paper))
+            .or_insert_with(Vec::new)
+            .push((row.preference, candidate));
+    }
+
+    let mut form_counter: HashMap<Form, u32> = HashMap::new();
+    for (_, mut prefs) in papers.drain() {
+        prefs.sort();
+        // the form runs for as long as the preferences are in unbroken sequence
+        let mut form = Vec::with_capacity(prefs.len());
+        for (idx, &(pref, candidate)) in prefs.iter().enumerate() {
+            if pref != (idx + 1) as u32 {
+                break;
+            }
+            if let Some(&(next, _)) = prefs.get(idx + 1) {
+                if next == pref {
+                    break;
+                }
+            }
+            form.push(candidate);
+        }
+        if form.is_empty() {
+            continue;
+        }
+        *form_counter.entry(form.into_iter().collect()).or_insert(0) += 1;
+    }
+
+    Ok(form_counter
+        .drain()
+        .map(|(form, count)| BallotState {
+            form,
+            count,
+            active_preference: 0,
+        })
+        .collect())
+}
+
+pub fn read_file(
+    filename: &str,
+    candidate_ids: &HashMap<u32, CandidateIndex>,
+) -> Result<Vec<BallotState>, Box<dyn Error>> {
+    let f = File::open(filename)?;
+    process_fd(f, candidate_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_btl_csv() {
+        let csv_data = r##"CandidateId,Preference,Batch,Paper
+101,1,1,1
+102,2,1,1
+103,3,1,1
+103,1,1,2
+101,2,1,2
+102,2,1,2
+102,2,1,3
+101,1,1,3
+103,3,1,3
+"##;
+        let mut candidate_ids = HashMap::new();
+        candidate_ids.insert(101, CandidateIndex(0));
+        candidate_ids.insert(102, CandidateIndex(1));
+        candidate_ids.insert(103, CandidateIndex(2));
+        let mut res = process_fd(csv_data.as_bytes(), &candidate_ids).unwrap();
+        res.sort_by_key(|bs| bs.count);
+        assert_eq!(res.len(), 2);
+        // the second paper has a doubled second preference, so stops at the first
+        assert_eq!(res[0].form.to_vec(), vec![CandidateIndex(2)]);
+        assert_eq!(res[0].count, 1);
+        assert_eq!(
+            res[1].form.to_vec(),
+            vec![CandidateIndex(0), CandidateIndex(1), CandidateIndex(2)]
+        );
+        assert_eq!(res[1].count, 2);
+    }
+}
@@ -7,6 +7,7 @@ extern crate csv;
 
 use std::error::Error;
 use std::fs::File;
+use std::io::Read;
 
 #[derive(Debug, Deserialize)]
 pub struct AECAllCandidateRow {
@@ -67,3 +68,70 @@ pub fn load(filename: &str, state: &str) -> Result<Vec<AECAllCandidateRow>, Box<
     });
     Ok(rows)
 }
+
+/// the candidates for the House of Representatives in `division`, read from `fd`, in ballot
+/// paper order
+pub(crate) fn process_house_fd(
+    fd: impl Read,
+    division: &str,
+) -> Result<Vec<AECAllCandidateRow>, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_reader(fd);
+    let mut rows: Vec<AECAllCandidateRow> = Vec::new();
+    let division = division.to_lowercase();
+    for result in rdr.deserialize() {
+        let record: AECAllCandidateRow = result?;
+        if record.nom_ty != "H" {
+            continue;
+        }
+        if record.div_nm.to_lowercase() != division {
+            continue;
+        }
+        rows.push(record);
+    }
+    rows.sort_by_key(|row| row.ballot_position);
+    Ok(rows)
+}
+
+/// the candidates for the House of Representatives in `division`, in ballot paper order
+pub fn load_house(
+    filename: &str,
+    division: &str,
+) -> Result<Vec<AECAllCandidateRow>, Box<dyn Error>> {
+    let f = File::open(filename)?;
+    process_house_fd(f, division)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn house_candidates_in_division() {
+        let csv_data = r##"txn_nm,nom_ty,state_ab,div_nm,ticket,ballot_position,surname,ballot_given_nm,party_ballot_nm,occupation,address_1,address_2,postcode,suburb,address_state_ab,contact_work_ph,contact_home_ph,postal_address_1,postal_address_2,postal_suburb,postal_postcode,contact_fax,postal_state_ab,contact_mobile_no,contact_email
+2019 Federal Election,H,TAS,Clark,,2,BAGGINS,Frodo,Hobbits,,,,,,,,,,,,,,,,
+2019 Federal Election,H,TAS,Clark,,1,LION,Aslan,Narnians,,,,,,,,,,,,,,,,
+2019 Federal Election,H,TAS,Lyons,,1,WITCH,White,Winter,,,,,,,,,,,,,,,,
+2019 Federal Election,S,TAS,,A,1,GAMGEE,Sam,Hobbits,,,,,,,,,,,,,,,,
+2019 Federal Election,H,TAS,Clark,,3,BOMBADIL,Tom,,,,,,,,,,,,,,,,,
+"##;
+        let rows = process_house_fd(csv_data.as_bytes(), "clark").unwrap();
+        let names: Vec<(&str, &str, &str)> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.surname.as_str(),
+                    row.ballot_given_nm.as_str(),
+                    row.party_ballot_nm.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("LION", "Aslan", "Narnians"),
+                ("BAGGINS", "Frodo", "Hobbits"),
+                ("BOMBADIL", "Tom", ""),
+            ]
+        );
+    }
+}
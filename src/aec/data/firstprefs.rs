@@ -0,0 +1,37 @@
+//
+// Parse the Senate first preferences by state CSV file, for the number of papers marked
+// above the line for each group, in Senate elections before the 2016 reforms.
+// Example file: http://results.aec.gov.au/17496/Website/Downloads/SenateFirstPrefsByStateByVoteTypeDownload-17496.csv
+//
+
+use aec::data::read_download;
+use std::error::Error;
+use std::fs::File;
+
+#[derive(Debug, Deserialize)]
+struct AECFirstPrefsRow {
+    #[serde(rename = "StateAb")]
+    state_ab: String,
+    #[serde(rename = "Ticket")]
+    ticket: String,
+    /// the group's ticket votes are given ballot position zero
+    #[serde(rename = "BallotPosition")]
+    ballot_position: u32,
+    #[serde(rename = "TotalVotes")]
+    total_votes: u32,
+}
+
+/// the number of papers marked above the line for each group in `state`, by group letter
+pub fn load_ticket_votes(
+    filename: &str,
+    state: &str,
+) -> Result<Vec<(String, u32)>, Box<dyn Error>> {
+    let f = File::open(filename)?;
+    let rows: Vec<AECFirstPrefsRow> = read_download(f, "StateAb")?;
+    let state = state.to_lowercase();
+    Ok(rows
+        .into_iter()
+        .filter(|row| row.state_ab.to_lowercase() == state && row.ballot_position == 0)
+        .map(|row| (row.ticket, row.total_votes))
+        .collect())
+}
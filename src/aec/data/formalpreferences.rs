@@ -2,33 +2,356 @@
 // Parse the formal preferences CSV file
 // Example file: http://results.aec.gov.au/20499/Website/External/aec-senate-formalpreferences-20499-NT.zip
 //
+// The 2016 file holds the preferences for each paper in a single quoted "Preferences" field:
+// the boxes above the line, and then the squares below the line, in ballot paper order.
+// From 2019, there is a column for each box and each square, labelled with the ticket:
+//   State,Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,Paper No,A:Party,...,A:SURNAME Given,...
+//
 
 extern crate csv;
 extern crate flate2;
 
 use defs::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::iter;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// a formal preferences file, or a line within it, which couldn't be read
+pub struct ParseError {
+    pub file: String,
+    /// the line number, counting from one; `None` if the file couldn't be read at all
+    pub line: Option<usize>,
+    /// the offending line
+    pub text: String,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "{}: line {}: {}: {}",
+                self.file, line, self.message, self.text
+            ),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// what to do with a line of the file which can't be parsed
+pub enum BadLines {
+    /// stop reading the file, and return the error
+    #[default]
+    Abort,
+    /// skip the line, and carry on; the lines skipped are returned with the papers
+    Skip,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+/// why a paper is informal
+pub enum InformalReason {
+    /// there are no preferences on the paper
+    Blank,
+    /// the first preference, or a later number needed for the paper to be formal, is missing
+    SkippedNumber,
+    /// the first preference, or a later number needed for the paper to be formal, is repeated
+    Duplicate,
+    /// below the line, fewer than six preferences in unbroken sequence, with no marks above
+    /// the line to save the paper
+    TooFewPreferences,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// the formality of a paper under section 268A (as amended in 2016), and the savings
+/// provisions of section 269
+pub enum Formality {
+    /// at least six preferences in unbroken sequence below the line, which take precedence
+    /// over any preferences above the line
+    Btl,
+    /// at least a first preference above the line, with nothing marked below the line
+    Atl,
+    /// marked below the line, but not formally; saved by a first preference above the line
+    BtlSavedByAtl,
+    Informal(InformalReason),
+}
+
+/// a tally of the formality of the papers in a formal preferences file, so that the AEC's
+/// decisions on formality can be checked against the Act
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FormalityReport {
+    pub btl: u32,
+    pub atl: u32,
+    pub btl_saved_by_atl: u32,
+    pub informal: HashMap<InformalReason, u32>,
+}
+
+impl FormalityReport {
+    fn record(&mut self, formality: Formality) {
+        match formality {
+            Formality::Btl => self.btl += 1,
+            Formality::Atl => self.atl += 1,
+            Formality::BtlSavedByAtl => self.btl_saved_by_atl += 1,
+            Formality::Informal(reason) => *self.informal.entry(reason).or_insert(0) += 1,
+        }
+    }
+
+    /// the number of papers informal for `reason`
+    pub fn informal_for(&self, reason: InformalReason) -> u32 {
+        self.informal.get(&reason).cloned().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// the papers giving their first preference to a group, above and below the line
+pub struct GroupStatistics {
+    pub atl: u32,
+    pub btl: u32,
+}
+
+/// statistics on how the formal papers in a formal preferences file were marked. the depth
+/// of a paper is the number of preferences which count towards its form: boxes above the
+/// line for a paper formal above the line, squares below the line otherwise. the marks are
+/// every box or square numbered, whether or not they count towards the form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BallotStatistics {
+    pub papers: u32,
+    /// the number of distinct forms among the papers
+    pub unique_forms: u32,
+    /// by group, in ballot paper order
+    pub groups: Vec<GroupStatistics>,
+    /// the papers formal below the line with a first preference for an ungrouped candidate
+    pub ungrouped_btl: u32,
+    /// the number of papers formal above the line, by depth
+    pub atl_depth: BTreeMap<usize, u32>,
+    /// the number of papers formal below the line, by depth
+    pub btl_depth: BTreeMap<usize, u32>,
+    /// the number of formal papers with boxes marked above the line, by the number marked
+    pub atl_marked: BTreeMap<usize, u32>,
+    /// the number of formal papers with squares marked below the line, by the number marked
+    pub btl_marked: BTreeMap<usize, u32>,
+    /// the papers whose form numbers every candidate
+    pub complete: u32,
+    candidates: usize,
+    candidate_groups: HashMap<CandidateIndex, GroupIndex>,
+}
+
+impl BallotStatistics {
+    fn new(tickets: &[Vec<CandidateIndex>], candidates: usize) -> BallotStatistics {
+        let mut candidate_groups = HashMap::new();
+        for (idx, ticket) in tickets.iter().enumerate() {
+            for candidate in ticket {
+                candidate_groups.insert(*candidate, GroupIndex(idx as u16));
+            }
+        }
+        BallotStatistics {
+            papers: 0,
+            unique_forms: 0,
+            groups: vec![GroupStatistics::default(); tickets.len()],
+            ungrouped_btl: 0,
+            atl_depth: BTreeMap::new(),
+            btl_depth: BTreeMap::new(),
+            atl_marked: BTreeMap::new(),
+            btl_marked: BTreeMap::new(),
+            complete: 0,
+            candidates,
+            candidate_groups,
+        }
+    }
+
+    /// record a formal paper, as it was left in `parser`, with the form it expanded to
+    fn record(&mut self, parser: &PrefParser, formality: Formality, form: &[CandidateIndex]) {
+        match formality {
+            Formality::Btl => {
+                match self.candidate_groups.get(&form[0]) {
+                    Some(group) => self.groups[group.0 as usize].btl += 1,
+                    None => self.ungrouped_btl += 1,
+                }
+                *self.btl_depth.entry(form.len()).or_insert(0) += 1;
+            }
+            Formality::Atl | Formality::BtlSavedByAtl => {
+                // the marks are sorted, and the form is formal, so the first is the first
+                // preference
+                self.groups[(parser.atl[0].1).0 as usize].atl += 1;
+                *self.atl_depth.entry(parser.atl_depth()).or_insert(0) += 1;
+            }
+            Formality::Informal(_) => return,
+        }
+        if !parser.atl.is_empty() {
+            *self.atl_marked.entry(parser.atl.len()).or_insert(0) += 1;
+        }
+        if !parser.btl.is_empty() {
+            *self.btl_marked.entry(parser.btl.len()).or_insert(0) += 1;
+        }
+        self.papers += 1;
+        if form.len() == self.candidates {
+            self.complete += 1;
+        }
+    }
+
+    /// the proportion of the papers whose form numbers every candidate
+    pub fn complete_share(&self) -> f64 {
+        if self.papers == 0 {
+            0.0
+        } else {
+            f64::from(self.complete) / f64::from(self.papers)
+        }
+    }
+}
+
+/// the papers read from a formal preferences file
+#[derive(Debug)]
+pub struct FormalPreferences {
+    /// the formal papers
+    pub ballot_states: Vec<BallotState>,
+    /// the lines skipped under `BadLines::Skip`
+    pub skipped: Vec<ParseError>,
+    pub formality: FormalityReport,
+    pub statistics: BallotStatistics,
+}
+
 // a voter's numerical preference for a candidate
 // if valid, it ranges from 1..N where N is the number of candidates
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-struct CandidatePreference(pub u8);
+struct CandidatePreference(pub u16);
 
 // a voter's numerical preference for a group
 // if valid, it ranges from 1..N where N is the number of groups
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-struct GroupPreference(pub u8);
+struct GroupPreference(pub u16);
 
-fn pref_to_u8(pref: &str) -> u8 {
+fn pref_to_u16(pref: &str) -> Result<u16, String> {
     if pref == "*" || pref == "/" {
-        1
+        Ok(1)
     } else {
-        pref.parse::<u8>().unwrap()
+        pref.parse::<u16>()
+            .map_err(|_| format!("invalid preference {:?}", pref))
+    }
+}
+
+/// what a field of the preferences holds: the box above the line for a group, or the square
+/// below the line for a candidate
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Column {
+    Group(GroupIndex),
+    Candidate(CandidateIndex),
+}
+
+/// the position of a ticket in ballot paper order: A, B, ... Z, AA, AB, ...
+fn ticket_position(ticket: &str) -> Option<usize> {
+    if ticket.is_empty() || !ticket.bytes().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut offset = 0;
+    let mut span = 1;
+    for _ in 1..ticket.len() {
+        span *= 26;
+        offset += span;
+    }
+    let value = ticket
+        .bytes()
+        .fold(0, |acc, c| acc * 26 + usize::from(c - b'A'));
+    Some(offset + value)
+}
+
+/// the label of the ticket at `position` in ballot paper order; the inverse of
+/// `ticket_position`
+pub fn ticket_label(position: usize) -> String {
+    let mut label = Vec::new();
+    let mut remaining = position + 1;
+    while remaining > 0 {
+        remaining -= 1;
+        label.push(b'A' + (remaining % 26) as u8);
+        remaining /= 26;
+    }
+    label.reverse();
+    String::from_utf8(label).unwrap()
+}
+
+/// a candidate's name as it appears in the labels of the 2019 layout: "SURNAME Given",
+/// from the "SURNAME, Given" of the candidate data
+fn label_name(name: &str) -> String {
+    name.replacen(", ", " ", 1).trim().to_string()
+}
+
+/// the columns of the 2019 layout, from the labels in its header: a box above the line for
+/// each group ("A:Party"), followed by a square below the line for each candidate
+/// ("A:SURNAME Given"), with the ungrouped candidates labelled "UG". each candidate's
+/// label must match their name in `names`
+fn labelled_columns(
+    labels: &[&str],
+    tickets: &[Vec<CandidateIndex>],
+    names: &[String],
+) -> Result<Vec<Column>, String> {
+    let candidates = names.len();
+    if labels.len() != tickets.len() + candidates {
+        return Err(format!(
+            "expected {} preference columns, found {}",
+            tickets.len() + candidates,
+            labels.len()
+        ));
+    }
+    let ungrouped: Vec<CandidateIndex> = (0..candidates)
+        .map(|idx| CandidateIndex(idx as u16))
+        .filter(|c| !tickets.iter().any(|t| t.contains(c)))
+        .collect();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    let mut columns = Vec::with_capacity(labels.len());
+    for (idx, label) in labels.iter().enumerate() {
+        let ticket = match label.find(':') {
+            Some(colon) => &label[..colon],
+            None => return Err(format!("column has no ticket: {}", label)),
+        };
+        if idx < tickets.len() {
+            if ticket_position(ticket) != Some(idx) {
+                return Err(format!("unexpected column above the line: {}", label));
+            }
+            columns.push(Column::Group(GroupIndex(idx as u16)));
+            continue;
+        }
+        let group = if ticket == "UG" {
+            &ungrouped
+        } else {
+            match ticket_position(ticket).and_then(|g| tickets.get(g)) {
+                Some(group) => group,
+                None => return Err(format!("unknown ticket: {}", label)),
+            }
+        };
+        let position = seen.entry(ticket).or_insert(0);
+        let candidate = match group.get(*position) {
+            Some(candidate) => *candidate,
+            None => return Err(format!("unexpected column below the line: {}", label)),
+        };
+        let name = &names[candidate.0 as usize];
+        if label[ticket.len() + 1..] != label_name(name) {
+            return Err(format!("column {} is for candidate {}", label, name));
+        }
+        columns.push(Column::Candidate(candidate));
+        *position += 1;
+    }
+    Ok(columns)
+}
+
+/// where the preferences, in sorted order, first fail to be an unbroken sequence from one;
+/// and why. `None` if the sequence is unbroken
+fn sequence_break(prefs: &[u16]) -> Option<(usize, InformalReason)> {
+    for (idx, pref) in prefs.iter().enumerate() {
+        if usize::from(*pref) != idx + 1 {
+            return Some((idx, InformalReason::SkippedNumber));
+        }
+        if prefs.get(idx + 1) == Some(pref) {
+            return Some((idx, InformalReason::Duplicate));
+        }
     }
+    None
 }
 
 type ATLPref = (GroupPreference, GroupIndex);
@@ -37,6 +360,10 @@ type ResolvedPrefs = Vec<CandidateIndex>;
 
 struct PrefParser {
     ticket_forms: Vec<Vec<CandidateIndex>>,
+    /// what each field holds; if `None`, the boxes for each group in order, followed by the
+    /// squares for each candidate in order
+    columns: Option<Vec<Column>>,
+    candidates: usize,
     atl: Vec<ATLPref>,
     btl: Vec<BTLPref>,
 }
@@ -45,32 +372,45 @@ impl PrefParser {
     fn new(tickets: &[Vec<CandidateIndex>], candidates: usize) -> PrefParser {
         PrefParser {
             ticket_forms: tickets.to_vec(),
+            columns: None,
+            candidates,
             atl: Vec::with_capacity(tickets.len()),
             btl: Vec::with_capacity(candidates),
         }
     }
 
-    fn clear(self: &mut Self) {
+    fn with_columns(
+        tickets: &[Vec<CandidateIndex>],
+        candidates: usize,
+        columns: Vec<Column>,
+    ) -> PrefParser {
+        PrefParser {
+            columns: Some(columns),
+            ..PrefParser::new(tickets, candidates)
+        }
+    }
+
+    fn clear(&mut self) {
         self.atl.clear();
         self.btl.clear();
     }
 
-    fn sort(self: &mut Self) {
+    fn sort(&mut self) {
         self.atl.sort();
         self.btl.sort();
     }
 
-    fn parse(self: &mut Self, pref: &str, mut form_buf: &mut ResolvedPrefs) {
+    fn parse(&mut self, pref: &str, form_buf: &mut ResolvedPrefs) -> Result<Formality, String> {
         self.clear();
-        self.parse_line(pref);
+        self.parse_line(pref)?;
         self.sort();
-        self.expand(&mut form_buf);
+        Ok(self.expand(form_buf))
     }
 
     // note: this function could be a lot neater, or just use the csv library, but
     // it's performance critical and so is hand optimised. we can assume that we're
     // plain ASCII, that the field values are either empty or are a smallish integer
-    fn parse_line(self: &mut Self, prefs: &str) {
+    fn parse_line(&mut self, prefs: &str) -> Result<(), String> {
         let mut field = 0;
         let mut from = 0;
         let tickets = self.ticket_forms.len();
@@ -89,15 +429,23 @@ impl PrefParser {
             };
             if term {
                 if upto - from > 0 {
-                    let pref = pref_to_u8(&prefs[from..upto]);
-                    if field < tickets {
-                        self.atl
-                            .push((GroupPreference(pref), GroupIndex(field as u8)));
-                    } else {
-                        self.btl.push((
-                            CandidatePreference(pref),
-                            CandidateIndex((field - tickets) as u8),
-                        ));
+                    let pref = pref_to_u16(&prefs[from..upto])?;
+                    let column = match self.columns {
+                        Some(ref columns) => match columns.get(field) {
+                            Some(column) => *column,
+                            None => return Err("more preferences than columns".to_string()),
+                        },
+                        None if field < tickets => Column::Group(GroupIndex(field as u16)),
+                        None if field < tickets + self.candidates => {
+                            Column::Candidate(CandidateIndex((field - tickets) as u16))
+                        }
+                        None => return Err("more preferences than columns".to_string()),
+                    };
+                    match column {
+                        Column::Group(group) => self.atl.push((GroupPreference(pref), group)),
+                        Column::Candidate(candidate) => {
+                            self.btl.push((CandidatePreference(pref), candidate))
+                        }
                     }
                 }
                 field += 1;
@@ -108,9 +456,10 @@ impl PrefParser {
             }
             upto += 1;
         }
+        Ok(())
     }
 
-    fn expand_btl(self: &Self, form_buf: &mut ResolvedPrefs) {
+    fn expand_btl(&self, form_buf: &mut ResolvedPrefs) {
         let left = self.btl.iter();
         let right = self.btl.iter().map(Some).skip(1).chain(iter::once(None));
         let combo = left.zip(right).enumerate();
@@ -118,7 +467,7 @@ impl PrefParser {
         // Validate below-the-line preferences. If these are valid, they take
         // precedence over any above-the-line preferences.
         for (idx, (pref, next_pref)) in combo {
-            if pref.0 != CandidatePreference((idx + 1) as u8) {
+            if usize::from((pref.0).0) != idx + 1 {
                 break;
             }
             // look ahead: we can't have double preferences
@@ -131,13 +480,13 @@ impl PrefParser {
         }
     }
 
-    fn expand_atl(self: &Self, form_buf: &mut ResolvedPrefs) {
+    fn expand_atl(&self, form_buf: &mut ResolvedPrefs) {
         let left = self.atl.iter();
         let right = self.atl.iter().map(Some).skip(1).chain(iter::once(None));
         let combo = left.zip(right).enumerate();
 
         for (idx, (pref, next_pref)) in combo {
-            if pref.0 != GroupPreference((idx + 1) as u8) {
+            if usize::from((pref.0).0) != idx + 1 {
                 break;
             }
             // look ahead: we can't have double preferences
@@ -150,39 +499,212 @@ impl PrefParser {
         }
     }
 
-    fn expand(self: &Self, mut form_buf: &mut ResolvedPrefs) {
+    /// the number of boxes marked above the line which count towards the form
+    fn atl_depth(&self) -> usize {
+        let prefs: Vec<u16> = self.atl.iter().map(|p| (p.0).0).collect();
+        match sequence_break(&prefs) {
+            Some((idx, _)) => idx,
+            None => prefs.len(),
+        }
+    }
+
+    fn expand(&self, form_buf: &mut ResolvedPrefs) -> Formality {
         // if we have at least six BTL prefrences, we have a valid form
-        self.expand_btl(&mut form_buf);
-        if form_buf.len() < 6 {
-            // we don't have a valid BTL form, validate and expand above-the-line
-            // preferences
-            form_buf.clear();
-            self.expand_atl(&mut form_buf);
+        self.expand_btl(form_buf);
+        if form_buf.len() >= 6 {
+            return Formality::Btl;
+        }
+        // we don't have a valid BTL form, validate and expand above-the-line
+        // preferences
+        form_buf.clear();
+        self.expand_atl(form_buf);
+        if form_buf.is_empty() {
+            Formality::Informal(self.informal_reason())
+        } else if self.btl.is_empty() {
+            Formality::Atl
+        } else {
+            Formality::BtlSavedByAtl
+        }
+    }
+
+    /// why a paper without a formal form is informal. if anything is marked above the line,
+    /// the reason is taken from those marks, as they would have saved the paper
+    fn informal_reason(&self) -> InformalReason {
+        if !self.atl.is_empty() {
+            let prefs: Vec<u16> = self.atl.iter().map(|p| (p.0).0).collect();
+            return match sequence_break(&prefs) {
+                Some((_, reason)) => reason,
+                None => InformalReason::SkippedNumber,
+            };
+        }
+        if self.btl.is_empty() {
+            return InformalReason::Blank;
+        }
+        let prefs: Vec<u16> = self.btl.iter().map(|p| (p.0).0).collect();
+        match sequence_break(&prefs) {
+            Some((idx, reason)) if idx < 6 => reason,
+            _ => InformalReason::TooFewPreferences,
+        }
+    }
+}
+
+/// the layout of the file, detected from its header
+#[derive(Debug, PartialEq, Eq)]
+enum Layout {
+    /// 2016: the preferences are in the last, quoted, field
+    Preferences,
+    /// 2019 onwards: the preferences follow the leading fields, a column for each
+    Columns,
+}
+
+/// the fields preceding the preferences in the 2019 layout
+const LEADING_FIELDS: usize = 6;
+
+/// the labels in the header, which may be quoted, and contain commas
+fn header_labels(header: &str) -> Result<Vec<String>, String> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(header.as_bytes());
+    match rdr.records().next() {
+        Some(Ok(record)) => Ok(record.iter().map(|label| label.to_string()).collect()),
+        Some(Err(e)) => Err(format!("couldn't read header: {}", e)),
+        None => Err("empty header".to_string()),
+    }
+}
+
+fn read_header(
+    header: &str,
+    tickets: &[Vec<CandidateIndex>],
+    names: &[String],
+) -> Result<(Layout, PrefParser), String> {
+    let candidates = names.len();
+    check_candidate_count(candidates)?;
+    let labels = header_labels(header)?;
+    let labels: Vec<&str> = labels.iter().map(|label| label.as_str()).collect();
+    if labels.last() == Some(&"Preferences") {
+        return Ok((Layout::Preferences, PrefParser::new(tickets, candidates)));
+    }
+    if labels.len() > LEADING_FIELDS
+        && labels[..3] == ["State", "Division", "Vote Collection Point Name"]
+    {
+        let columns = labelled_columns(&labels[LEADING_FIELDS..], tickets, names)?;
+        return Ok((
+            Layout::Columns,
+            PrefParser::with_columns(tickets, candidates, columns),
+        ));
+    }
+    Err(format!("unrecognised header: {}", header))
+}
+
+/// the position of the first preference in a line of the 2019 layout, following the
+/// leading fields; which may be quoted, and contain commas
+fn preferences_start(line: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut fields = 0;
+    for (idx, c) in line.bytes().enumerate() {
+        match c {
+            b'"' => quoted = !quoted,
+            b',' if !quoted => {
+                fields += 1;
+                if fields == LEADING_FIELDS {
+                    return Some(idx + 1);
+                }
+            }
+            _ => {}
         }
     }
+    None
+}
+
+/// the preferences in a line of the file, in the given layout
+fn line_preferences<'a>(layout: &Layout, line: &'a str) -> Result<&'a str, String> {
+    match layout {
+        Layout::Preferences => match line.find('"') {
+            Some(quote) if line.len() > quote + 1 && line.ends_with('"') => {
+                Ok(&line[quote + 1..line.len() - 1])
+            }
+            _ => Err("no quoted preferences".to_string()),
+        },
+        Layout::Columns => match preferences_start(line) {
+            Some(start) => Ok(&line[start..]),
+            None => Err("too few fields".to_string()),
+        },
+    }
 }
 
 fn process_fd(
     fd: impl std::io::Read,
+    filename: &str,
     tickets: &[Vec<CandidateIndex>],
-    candidates: usize,
-) -> Vec<BallotState> {
+    names: &[String],
+    bad_lines: BadLines,
+) -> Result<FormalPreferences, ParseError> {
+    let candidates = names.len();
+    let error = |line: Option<usize>, text: &str, message: String| ParseError {
+        file: filename.to_string(),
+        line,
+        text: text.to_string(),
+        message,
+    };
     let rdr = BufReader::new(fd);
-    let mut form_counter: HashMap<ResolvedPrefs, u32> = HashMap::new();
-    let mut parser = PrefParser::new(&tickets, candidates);
-
-    for r in rdr.lines().skip(2) {
-        let line = r.unwrap();
-        let pref: &str = &line[(line.find('\"').unwrap() + 1)..line.len() - 1];
-        let mut form_buf: ResolvedPrefs = Vec::with_capacity(candidates);
+    let mut form_counter: HashMap<Form, u32> = HashMap::new();
+    let mut skipped = Vec::new();
+    let mut formality = FormalityReport::default();
+    let mut statistics = BallotStatistics::new(tickets, candidates);
+    let mut lines = rdr.lines();
+    let header = match lines.next() {
+        Some(Ok(header)) => header,
+        Some(Err(e)) => return Err(error(Some(1), "", e.to_string())),
+        None => return Err(error(None, "", "file is empty".to_string())),
+    };
+    let (layout, mut parser) = match read_header(&header, tickets, names) {
+        Ok(parsed) => parsed,
+        Err(e) => return Err(error(Some(1), &header, e)),
+    };
 
-        parser.parse(pref, &mut form_buf);
-        assert!(!form_buf.is_empty());
+    let mut form_buf: ResolvedPrefs = Vec::with_capacity(candidates);
+    for (idx, r) in lines.enumerate() {
+        // the header is the first line
+        let number = idx + 2;
+        let line = match r {
+            Ok(line) => line,
+            // the rest of the file can't be read, so there's no skipping the line
+            Err(e) => return Err(error(Some(number), "", e.to_string())),
+        };
+        // the header may be underlined
+        if line.starts_with("--") {
+            continue;
+        }
+        form_buf.clear();
+        let parsed =
+            line_preferences(&layout, &line).and_then(|pref| parser.parse(pref, &mut form_buf));
+        let paper_formality = match parsed {
+            Ok(paper_formality) => paper_formality,
+            Err(e) => {
+                let e = error(Some(number), &line, e);
+                match bad_lines {
+                    BadLines::Abort => return Err(e),
+                    BadLines::Skip => {
+                        skipped.push(e);
+                        continue;
+                    }
+                }
+            }
+        };
+        formality.record(paper_formality);
+        statistics.record(&parser, paper_formality, &form_buf);
+        // informal papers are tallied, but have no part in the count
+        if form_buf.is_empty() {
+            continue;
+        }
 
-        let counter = form_counter.entry(form_buf).or_insert(0);
+        let counter = form_counter
+            .entry(form_buf.iter().cloned().collect())
+            .or_insert(0);
         *counter += 1;
     }
 
+    statistics.unique_forms = form_counter.len() as u32;
     let v: Vec<BallotState> = form_counter
         .drain()
         .map(|(form, count)| BallotState {
@@ -191,17 +713,35 @@ fn process_fd(
             active_preference: 0,
         })
         .collect();
-    v
+    Ok(FormalPreferences {
+        ballot_states: v,
+        skipped,
+        formality,
+        statistics,
+    })
 }
 
+/// read the papers from a formal preferences file, for the candidates with the given `names`
+/// ("SURNAME, Given"), in ballot paper order
 pub fn read_file(
     filename: &str,
     tickets: &[Vec<CandidateIndex>],
-    candidates: usize,
-) -> Vec<BallotState> {
-    let f = File::open(filename).unwrap();
+    names: &[String],
+    bad_lines: BadLines,
+) -> Result<FormalPreferences, ParseError> {
+    let f = match File::open(filename) {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(ParseError {
+                file: filename.to_string(),
+                line: None,
+                text: String::new(),
+                message: e.to_string(),
+            });
+        }
+    };
     let gf = flate2::read::GzDecoder::new(f);
-    process_fd(gf, tickets, candidates)
+    process_fd(gf, filename, tickets, names, bad_lines)
 }
 
 #[cfg(test)]
@@ -215,8 +755,9 @@ mod tests {
         btl_expected: &Vec<BTLPref>,
     ) {
         let dummy = vec![Vec::new(); tickets];
-        let mut parser = PrefParser::new(&dummy, 0);
-        parser.parse_line(&line);
+        let candidates = line.split(',').count() - tickets;
+        let mut parser = PrefParser::new(&dummy, candidates);
+        parser.parse_line(line).unwrap();
         parser.sort();
         assert!(*atl_expected == parser.atl);
         assert!(*btl_expected == parser.btl);
@@ -235,6 +776,17 @@ mod tests {
         parse_prefstring(3, &String::from("2,1,3,,,"), &atl, &btl);
     }
 
+    #[test]
+    fn prefstring_too_many_fields() {
+        let dummy = vec![Vec::new(); 2];
+        let mut parser = PrefParser::new(&dummy, 3);
+        assert!(parser.parse_line("1,,,,,").is_ok());
+        assert_eq!(
+            parser.parse_line("1,,,,,2"),
+            Err("more preferences than columns".to_string())
+        );
+    }
+
     #[test]
     fn prefstring_atl_and_btl() {
         let atl: Vec<ATLPref> = [(GroupPreference(1), GroupIndex(2))].to_vec();
@@ -309,6 +861,8 @@ mod tests {
 
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl: Vec::new(),
         };
@@ -336,6 +890,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 0,
             atl,
             btl: Vec::new(),
         };
@@ -361,6 +917,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl: Vec::new(),
         };
@@ -386,6 +944,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl: Vec::new(),
         };
@@ -405,6 +965,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms: Vec::new(),
+            columns: None,
+            candidates: 6,
             atl: Vec::new(),
             btl,
         };
@@ -419,6 +981,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms: Vec::new(),
+            columns: None,
+            candidates: 6,
             atl: Vec::new(),
             btl,
         };
@@ -438,6 +1002,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms: Vec::new(),
+            columns: None,
+            candidates: 6,
             atl: Vec::new(),
             btl,
         };
@@ -459,6 +1025,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms: Vec::new(),
+            columns: None,
+            candidates: 6,
             atl: Vec::new(),
             btl,
         };
@@ -480,11 +1048,13 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms: Vec::new(),
+            columns: None,
+            candidates: 6,
             atl: Vec::new(),
             btl,
         };
         parser.expand_btl(&mut form_buf);
-        assert!(form_buf == []);
+        assert!(form_buf.is_empty());
     }
 
     #[test]
@@ -501,6 +1071,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms: Vec::new(),
+            columns: None,
+            candidates: 6,
             atl: Vec::new(),
             btl,
         };
@@ -524,6 +1096,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl,
         };
@@ -550,6 +1124,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl,
         };
@@ -574,6 +1150,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl,
         };
@@ -600,6 +1178,8 @@ mod tests {
         let mut form_buf = Vec::new();
         let parser = PrefParser {
             ticket_forms,
+            columns: None,
+            candidates: 6,
             atl,
             btl,
         };
@@ -607,6 +1187,13 @@ mod tests {
         assert!(form_buf == [CandidateIndex(0), CandidateIndex(1)]);
     }
 
+    /// names for `count` candidates, for a file in the 2016 layout, which has no labels
+    fn names(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|idx| format!("CANDIDATE, {}", idx))
+            .collect()
+    }
+
     fn stringify_ballotstates(ballot_states: &[BallotState]) -> String {
         let mut stringed: Vec<String> = ballot_states.iter().map(|x| format!("{:?}", x)).collect();
         stringed.sort();
@@ -628,7 +1215,297 @@ Middle Earth,Rohan,42,43,1,"1,,,,,,,,"
             [CandidateIndex(3), CandidateIndex(4), CandidateIndex(5)].to_vec(),
         ]
         .to_vec();
-        let res = process_fd(fd, tickets, 6);
-        assert!(stringify_ballotstates(&res) == r##"["BallotState { form: [CandidateIndex(0), CandidateIndex(1), CandidateIndex(2), CandidateIndex(3), CandidateIndex(4), CandidateIndex(5)], count: 2, active_preference: 0 }", "BallotState { form: [CandidateIndex(0), CandidateIndex(1)], count: 1, active_preference: 0 }"]"##);
+        let res = process_fd(fd, "test.csv", tickets, &names(6), BadLines::Abort)
+            .unwrap()
+            .ballot_states;
+        assert!(
+            stringify_ballotstates(&res)
+                == r##"["BallotState { form: [CandidateIndex(0), CandidateIndex(1), CandidateIndex(2), CandidateIndex(3), CandidateIndex(4), CandidateIndex(5)], count: 2, active_preference: 0 }", "BallotState { form: [CandidateIndex(0), CandidateIndex(1)], count: 1, active_preference: 0 }"]"##
+        );
+    }
+
+    #[test]
+    fn ticket_positions() {
+        assert_eq!(ticket_position("A"), Some(0));
+        assert_eq!(ticket_position("Z"), Some(25));
+        assert_eq!(ticket_position("AA"), Some(26));
+        assert_eq!(ticket_position("BA"), Some(52));
+        assert_eq!(ticket_position("a"), None);
+        for position in &[0, 25, 26, 52, 701, 702] {
+            assert_eq!(ticket_position(&ticket_label(*position)), Some(*position));
+        }
+        assert_eq!(ticket_label(27), "AB");
+    }
+
+    #[test]
+    fn parse_aec_csv_2019() {
+        let csv_data = r##"State,Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,Paper No,A:Narnians,B:Hobbits,A:LION Aslan,A:WITCH White,B:BAGGINS Frodo,B:GAMGEE Sam,UG:BOMBADIL Tom,UG:GOLLUM
+------,--------,--------------------------,------------------------,--------,--------,------,------
+NT,Lingiari,"Cupboard, Wardrobe",1,1,1,1,2,,,,,,
+NT,Lingiari,Rohan,42,43,1,,,6,5,4,3,2,1
+NT,Lingiari,Rohan,42,43,2,,1,,,,,,
+"##;
+        let tickets: &Vec<Vec<CandidateIndex>> = &[
+            [CandidateIndex(0), CandidateIndex(1)].to_vec(),
+            [CandidateIndex(2), CandidateIndex(3)].to_vec(),
+        ]
+        .to_vec();
+        let names: Vec<String> = [
+            "LION, Aslan",
+            "WITCH, White",
+            "BAGGINS, Frodo",
+            "GAMGEE, Sam",
+            "BOMBADIL, Tom",
+            "GOLLUM, ",
+        ]
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+        let res = process_fd(
+            csv_data.as_bytes(),
+            "test.csv",
+            tickets,
+            &names,
+            BadLines::Abort,
+        )
+        .unwrap()
+        .ballot_states;
+        assert!(
+            stringify_ballotstates(&res)
+                == r##"["BallotState { form: [CandidateIndex(0), CandidateIndex(1), CandidateIndex(2), CandidateIndex(3)], count: 1, active_preference: 0 }", "BallotState { form: [CandidateIndex(2), CandidateIndex(3)], count: 1, active_preference: 0 }", "BallotState { form: [CandidateIndex(5), CandidateIndex(4), CandidateIndex(3), CandidateIndex(2), CandidateIndex(1), CandidateIndex(0)], count: 1, active_preference: 0 }"]"##
+        );
+    }
+
+    #[test]
+    fn parse_aec_csv_2019_quoted_labels() {
+        let csv_data = r##"State,Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,Paper No,"A:Shooters, Fishers and Farmers",B:Hobbits,A:LION Aslan,B:BAGGINS Frodo
+------,--------,--------------------------,------------------------,--------,--------,------,------
+NT,Lingiari,Rohan,42,43,1,,1,,
+NT,Lingiari,Rohan,42,43,2,2,1,,
+"##;
+        let tickets = vec![vec![CandidateIndex(0)], vec![CandidateIndex(1)]];
+        let res = process_fd(
+            csv_data.as_bytes(),
+            "test.csv",
+            &tickets,
+            &["LION, Aslan".to_string(), "BAGGINS, Frodo".to_string()],
+            BadLines::Abort,
+        )
+        .unwrap()
+        .ballot_states;
+        let mut forms: Vec<Vec<CandidateIndex>> = res.iter().map(|bs| bs.form.to_vec()).collect();
+        forms.sort();
+        assert_eq!(
+            forms,
+            vec![
+                vec![CandidateIndex(1)],
+                vec![CandidateIndex(1), CandidateIndex(0)]
+            ]
+        );
+    }
+
+    #[test]
+    fn labelled_columns_mismatch() {
+        let tickets = vec![vec![CandidateIndex(0)], vec![CandidateIndex(1)]];
+        let names = vec!["LION, Aslan".to_string(), "BAGGINS, Frodo".to_string()];
+        let labels = ["A:Narnians", "B:Hobbits", "A:LION Aslan", "B:BAGGINS Frodo"];
+        assert!(labelled_columns(
+            &["B:Hobbits", "A:Narnians", "A:LION Aslan", "B:BAGGINS Frodo"],
+            &tickets,
+            &names
+        )
+        .is_err());
+        assert!(labelled_columns(&labels[..3], &tickets, &names).is_err());
+        assert_eq!(
+            labelled_columns(&labels, &tickets, &names),
+            Ok(vec![
+                Column::Group(GroupIndex(0)),
+                Column::Group(GroupIndex(1)),
+                Column::Candidate(CandidateIndex(0)),
+                Column::Candidate(CandidateIndex(1)),
+            ])
+        );
+    }
+
+    #[test]
+    fn labelled_columns_swapped_names() {
+        // each ticket has two candidates, whose names are in the wrong order
+        let tickets = vec![
+            vec![CandidateIndex(0), CandidateIndex(1)],
+            vec![CandidateIndex(2), CandidateIndex(3)],
+        ];
+        let names: Vec<String> = [
+            "LION, Aslan",
+            "WITCH, White",
+            "BAGGINS, Frodo",
+            "GAMGEE, Sam",
+        ]
+        .iter()
+        .map(|n| n.to_string())
+        .collect();
+        let labels = [
+            "A:Narnians",
+            "B:Hobbits",
+            "A:WITCH White",
+            "A:LION Aslan",
+            "B:BAGGINS Frodo",
+            "B:GAMGEE Sam",
+        ];
+        assert_eq!(
+            labelled_columns(&labels, &tickets, &names),
+            Err("column A:WITCH White is for candidate LION, Aslan".to_string())
+        );
+    }
+
+    const BAD_CSV: &str = r##"ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,Preferences
+------------,---------------------,---------------------,-------,-------,-----------
+Narnia,Cupboard,1,1,1,"1,,,,,,"
+Narnia,Cupboard,1,1,2,"x,,,,,,"
+Narnia,Cupboard,1,1,3,1,,,,,,
+Narnia,Cupboard,1,1,4,",,2,,,,"
+Narnia,Cupboard,1,1,5,"1,,65536,,,,"
+"##;
+
+    fn bad_lines_tickets() -> Vec<Vec<CandidateIndex>> {
+        vec![
+            vec![CandidateIndex(0), CandidateIndex(1)],
+            vec![CandidateIndex(2)],
+        ]
+    }
+
+    #[test]
+    fn parse_bad_line_aborts() {
+        let err = process_fd(
+            BAD_CSV.as_bytes(),
+            "test.csv",
+            &bad_lines_tickets(),
+            &names(4),
+            BadLines::Abort,
+        )
+        .unwrap_err();
+        assert_eq!(err.line, Some(4));
+        assert_eq!(err.text, r#"Narnia,Cupboard,1,1,2,"x,,,,,,""#);
+        assert_eq!(
+            err.to_string(),
+            r#"test.csv: line 4: invalid preference "x": Narnia,Cupboard,1,1,2,"x,,,,,,""#
+        );
+    }
+
+    #[test]
+    fn parse_bad_lines_skipped() {
+        let res = process_fd(
+            BAD_CSV.as_bytes(),
+            "test.csv",
+            &bad_lines_tickets(),
+            &names(4),
+            BadLines::Skip,
+        )
+        .unwrap();
+        assert_eq!(res.ballot_states.len(), 1);
+        // the paper at line 6 is informal, which is not an error
+        assert_eq!(res.formality.informal_for(InformalReason::SkippedNumber), 1);
+        let skipped: Vec<(Option<usize>, &str)> = res
+            .skipped
+            .iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (Some(4), "invalid preference \"x\""),
+                (Some(5), "no quoted preferences"),
+                (Some(7), "invalid preference \"65536\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_missing_file() {
+        let err = read_file("/nonexistent.csv.gz", &[], &[], BadLines::Abort).unwrap_err();
+        assert_eq!(err.line, None);
+    }
+
+    fn classify(tickets: usize, candidates: usize, line: &str) -> Formality {
+        let ticket_forms = vec![vec![CandidateIndex(0)]; tickets];
+        let mut parser = PrefParser::new(&ticket_forms, candidates);
+        let mut form_buf = Vec::new();
+        parser.parse(line, &mut form_buf).unwrap()
+    }
+
+    #[test]
+    fn formality_classification() {
+        assert_eq!(classify(2, 6, ",,1,2,3,4,5,6"), Formality::Btl);
+        assert_eq!(classify(2, 6, "1,2,1,2,3,4,5,6"), Formality::Btl);
+        assert_eq!(classify(2, 6, "1,,,,,,,"), Formality::Atl);
+        assert_eq!(classify(2, 6, "1,,1,2,3,4,5,"), Formality::BtlSavedByAtl);
+        assert_eq!(
+            classify(2, 6, ",,,,,,,"),
+            Formality::Informal(InformalReason::Blank)
+        );
+        assert_eq!(
+            classify(2, 6, "2,3,,,,,,"),
+            Formality::Informal(InformalReason::SkippedNumber)
+        );
+        assert_eq!(
+            classify(2, 6, "1,1,,,,,,"),
+            Formality::Informal(InformalReason::Duplicate)
+        );
+        assert_eq!(
+            classify(2, 6, ",,1,2,3,3,4,5"),
+            Formality::Informal(InformalReason::Duplicate)
+        );
+        assert_eq!(
+            classify(2, 6, ",,1,2,4,5,6,7"),
+            Formality::Informal(InformalReason::SkippedNumber)
+        );
+        assert_eq!(
+            classify(2, 6, ",,1,2,3,4,5,"),
+            Formality::Informal(InformalReason::TooFewPreferences)
+        );
+    }
+
+    #[test]
+    fn ballot_statistics() {
+        let csv_data = r##"ElectorateNm,VoteCollectionPointNm,VoteCollectionPointId,BatchNo,PaperNo,Preferences
+------------,---------------------,---------------------,-------,-------,-----------
+Narnia,Cupboard,1,1,1,"1,2,,,,,,"
+Narnia,Cupboard,1,1,2,"1,2,,,,,,"
+Narnia,Cupboard,1,1,3,",1,,,,,,"
+Narnia,Cupboard,1,1,4,",,1,2,3,4,5,6"
+Narnia,Cupboard,1,1,5,"1,,1,2,3,,,"
+Narnia,Cupboard,1,1,6,",,6,5,4,3,2,1"
+Narnia,Cupboard,1,1,7,",,,,,,,"
+"##;
+        let tickets = vec![
+            vec![CandidateIndex(0), CandidateIndex(1), CandidateIndex(2)],
+            vec![CandidateIndex(3), CandidateIndex(4)],
+        ];
+        let res = process_fd(
+            csv_data.as_bytes(),
+            "test.csv",
+            &tickets,
+            &names(6),
+            BadLines::Abort,
+        )
+        .unwrap();
+        let stats = res.statistics;
+        assert_eq!(stats.papers, 6);
+        assert_eq!(stats.unique_forms, 5);
+        assert_eq!(
+            stats.groups,
+            vec![
+                GroupStatistics { atl: 3, btl: 1 },
+                GroupStatistics { atl: 1, btl: 0 },
+            ]
+        );
+        // candidate 5 is ungrouped
+        assert_eq!(stats.ungrouped_btl, 1);
+        assert_eq!(stats.atl_depth, [(1, 2), (2, 2)].iter().cloned().collect());
+        assert_eq!(stats.btl_depth, [(6, 2)].iter().cloned().collect());
+        // the squares marked on the fifth paper are counted, though it is formal above the line
+        assert_eq!(stats.atl_marked, [(1, 2), (2, 2)].iter().cloned().collect());
+        assert_eq!(stats.btl_marked, [(3, 1), (6, 2)].iter().cloned().collect());
+        assert_eq!(stats.complete, 2);
+        assert!((stats.complete_share() - 1.0 / 3.0).abs() < 1e-9);
     }
 }
@@ -0,0 +1,65 @@
+//
+// Parse the group voting tickets CSV file, for Senate elections before the 2016 reforms.
+// Example file: http://results.aec.gov.au/17496/Website/Downloads/SenateGroupVotingTicketsDownload-17496.csv
+//
+
+use aec::data::read_download;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Debug, Deserialize)]
+pub struct AECGroupVotingTicketRow {
+    #[serde(rename = "State")]
+    pub state: String,
+    /// the group which lodged the ticket
+    #[serde(rename = "OwnerTicket")]
+    pub owner_ticket: String,
+    /// a group may lodge up to three tickets
+    #[serde(rename = "TicketNo")]
+    pub ticket_no: u32,
+    #[serde(rename = "CandidateID")]
+    pub candidate_id: u32,
+    #[serde(rename = "Surname")]
+    pub surname: String,
+    #[serde(rename = "GivenNm")]
+    pub given_nm: String,
+    /// the preference given to the candidate on this ticket
+    #[serde(rename = "PreferenceNo")]
+    pub preference_no: u32,
+}
+
+fn process_fd(fd: impl Read, state: &str) -> Result<Vec<AECGroupVotingTicketRow>, Box<dyn Error>> {
+    let rows: Vec<AECGroupVotingTicketRow> = read_download(fd, "State")?;
+    let state = state.to_lowercase();
+    Ok(rows
+        .into_iter()
+        .filter(|row| row.state.to_lowercase() == state)
+        .collect())
+}
+
+pub fn load(filename: &str, state: &str) -> Result<Vec<AECGroupVotingTicketRow>, Box<dyn Error>> {
+    let f = File::open(filename)?;
+    process_fd(f, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_gvt_csv() {
+        let csv_data = r##"2013 Federal Election Senate Group Voting Tickets - 17496
+State,OwnerGroupNm,OwnerTicket,TicketNo,CandidateID,Surname,GivenNm,PartyNm,PreferenceNo
+WA,Narnia Party,A,1,101,LION,Aslan,Narnia Party,1
+WA,Narnia Party,A,1,102,WITCH,Jadis,Winter Party,2
+TAS,Shire Party,A,1,201,BAGGINS,Frodo,Shire Party,1
+"##;
+        let rows = process_fd(csv_data.as_bytes(), "wa").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].owner_ticket, "A");
+        assert_eq!(rows[1].candidate_id, 102);
+        assert_eq!(rows[1].surname, "WITCH");
+        assert_eq!(rows[1].preference_no, 2);
+    }
+}
@@ -0,0 +1,138 @@
+//
+// Parse the House of Representatives formal preferences CSV file. Each row is a paper, with
+// its preferences for each candidate in ballot paper order in the quoted "Preferences" field.
+// The layout follows the Senate formal preferences file:
+//   State,Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,Paper No,Preferences
+//
+
+use aec::data::read_download;
+use defs::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Debug, Deserialize)]
+struct AECHousePreferenceRow {
+    #[serde(rename = "Division")]
+    division: String,
+    #[serde(rename = "Preferences")]
+    preferences: String,
+}
+
+/// the form of a paper: the candidates in order of preference, for as long as the preferences
+/// are in unbroken sequence. where every square but one is numbered in sequence, the remaining
+/// square is taken to have the last preference
+fn paper_form(preferences: &str, candidates: usize) -> Result<Vec<CandidateIndex>, Box<dyn Error>> {
+    let fields: Vec<&str> = preferences.split(',').collect();
+    if fields.len() != candidates {
+        return Err(format!(
+            "expected preferences for {} candidates: {}",
+            candidates, preferences
+        )
+        .into());
+    }
+    let mut prefs = Vec::with_capacity(candidates);
+    for (idx, field) in fields.iter().enumerate() {
+        if field.is_empty() {
+            continue;
+        }
+        let pref: usize = match field.parse() {
+            Ok(pref) => pref,
+            Err(_) => return Err(format!("invalid preference: {}", preferences).into()),
+        };
+        prefs.push((pref, CandidateIndex(idx as u16)));
+    }
+    prefs.sort();
+    let mut form = Vec::with_capacity(candidates);
+    for (idx, &(pref, candidate)) in prefs.iter().enumerate() {
+        if pref != idx + 1 {
+            break;
+        }
+        if let Some(&(next, _)) = prefs.get(idx + 1) {
+            if next == pref {
+                break;
+            }
+        }
+        form.push(candidate);
+    }
+    if form.len() + 1 == candidates && prefs.len() + 1 == candidates {
+        let last = (0..candidates)
+            .map(|idx| CandidateIndex(idx as u16))
+            .find(|c| !form.contains(c))
+            .unwrap();
+        form.push(last);
+    }
+    Ok(form)
+}
+
+fn process_fd(
+    fd: impl Read,
+    division: &str,
+    candidates: usize,
+) -> Result<Vec<BallotState>, Box<dyn Error>> {
+    check_candidate_count(candidates)?;
+    let rows: Vec<AECHousePreferenceRow> = read_download(fd, "State")?;
+    let division = division.to_lowercase();
+    let mut form_counter: HashMap<Form, u32> = HashMap::new();
+    for row in rows {
+        if row.division.to_lowercase() != division {
+            continue;
+        }
+        let form = paper_form(&row.preferences, candidates)?;
+        if form.is_empty() {
+            continue;
+        }
+        *form_counter.entry(form.into_iter().collect()).or_insert(0) += 1;
+    }
+    Ok(form_counter
+        .drain()
+        .map(|(form, count)| BallotState {
+            form,
+            count,
+            active_preference: 0,
+        })
+        .collect())
+}
+
+/// read the papers cast in `division`, which has `candidates` candidates
+pub fn read_file(
+    filename: &str,
+    division: &str,
+    candidates: usize,
+) -> Result<Vec<BallotState>, Box<dyn Error>> {
+    let f = File::open(filename)?;
+    process_fd(f, division, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_house_csv() {
+        let csv_data = r##"State,Division,Vote Collection Point Name,Vote Collection Point ID,Batch No,Paper No,Preferences
+------,--------,--------------------------,------------------------,--------,--------,-----------
+TAS,Clark,Hobart,1,1,1,"2,1,3"
+TAS,Clark,Hobart,1,1,2,"2,1,"
+TAS,Clark,Hobart,1,1,3,"1,,3"
+TAS,Lyons,Bothwell,2,1,1,"1,2,3"
+"##;
+        let mut res = process_fd(csv_data.as_bytes(), "Clark", 3).unwrap();
+        res.sort_by_key(|bs| bs.count);
+        let forms: Vec<(Vec<CandidateIndex>, u32)> = res
+            .into_iter()
+            .map(|bs| (bs.form.to_vec(), bs.count))
+            .collect();
+        assert_eq!(
+            forms,
+            vec![
+                (vec![CandidateIndex(0)], 1),
+                (
+                    vec![CandidateIndex(1), CandidateIndex(0), CandidateIndex(2)],
+                    2
+                ),
+            ]
+        );
+    }
+}
@@ -1,2 +1,42 @@
+pub mod btl;
 pub mod candidates;
+pub mod firstprefs;
 pub mod formalpreferences;
+pub mod gvt;
+pub mod housepreferences;
+
+extern crate csv;
+
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::io::Read;
+
+/// read the rows of an AEC download CSV file. these files may start with a line describing
+/// the event, before the header: any lines before the header, recognised by the name of
+/// its first column, are skipped
+pub fn read_download<T: DeserializeOwned>(
+    fd: impl Read,
+    first_column: &str,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(fd);
+    let mut header: Option<csv::StringRecord> = None;
+    let mut rows = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        match header {
+            Some(ref header) => rows.push(record.deserialize(Some(header))?),
+            None => {
+                if record.get(0) == Some(first_column) {
+                    header = Some(record);
+                }
+            }
+        }
+    }
+    if header.is_none() {
+        return Err(format!("no header found, expected a column named {}", first_column).into());
+    }
+    Ok(rows)
+}
@@ -1,23 +1,30 @@
+use aec::data::formalpreferences::BadLines;
+use defs::*;
+use nswlc::NswLegislativeCouncil;
+use rules::{CountingRules, QuotaFormula, RoundingPolicy, Scotland2007, Senate2016, SurplusMethod};
+use senate2013::TicketLot;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Deserialize)]
 struct Candidates {
-    senate: String,
     all: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct Dataset {
     preferences: String,
+    tickets: Option<String>,
+    first_preferences: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Tie {
-    tie: Vec<String>,
-    pick: String,
+#[derive(Debug, Deserialize, Clone)]
+pub struct Tie {
+    pub tie: Vec<String>,
+    pub pick: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,9 +33,23 @@ struct Count {
     dataset: String,
     description: String,
     vacancies: usize,
+    excluded: Option<Vec<String>>,
+    bulk_exclusion: Option<bool>,
+    surplus: Option<String>,
+    quota: Option<String>,
+    rounding: Option<String>,
+    rounding_places: Option<u32>,
+    tolerance: Option<f64>,
+    seed: Option<u64>,
+    seeds: Option<Vec<u64>>,
+    division: Option<String>,
+    vacating: Option<String>,
+    contesting: Option<Vec<String>>,
+    bad_lines: Option<String>,
     election_order_ties: Vec<Tie>,
     election_ties: Vec<Tie>,
     exclusion_ties: Vec<Tie>,
+    ticket_lots: Option<Vec<TicketLot>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,15 +79,245 @@ fn config_contents(input_file: &str) -> Result<Config, String> {
     Ok(config)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// the kind of count to be conducted
+pub enum CountMethod {
+    /// a count of the Senate
+    Senate,
+    /// a special count under section 282, with the `excluded` candidates (for example,
+    /// those found to be ineligible) treated as excluded from the start of the count
+    SpecialRecount,
+    /// a count of the Senate following a double dissolution, followed by a recount under
+    /// section 282 for half the vacancies, to allocate long and short terms
+    DoubleDissolution,
+    /// a count of the Senate before the 2016 reforms, where papers marked above the line
+    /// follow the group voting tickets lodged by each group
+    SenateGvt,
+    /// a count under Meek's method of STV
+    Meek,
+    /// a count of a Scottish local government election, under the 2007 Order
+    Scotland2007,
+    /// a count of the New South Wales Legislative Council, with surpluses transferred by
+    /// the random selection of papers
+    NswLegislativeCouncil,
+    /// a preferential count of a division of the House of Representatives, for a single
+    /// vacancy, with the two-candidate-preferred result
+    House,
+}
+
+impl CountMethod {
+    fn parse(method: &Option<String>) -> Result<CountMethod, String> {
+        match method.as_ref().map(|m| m.as_str()) {
+            None | Some("senate") | Some("senate2016") => Ok(CountMethod::Senate),
+            Some("special_recount") => Ok(CountMethod::SpecialRecount),
+            Some("double_dissolution") => Ok(CountMethod::DoubleDissolution),
+            Some("senate_gvt") => Ok(CountMethod::SenateGvt),
+            Some("meek") => Ok(CountMethod::Meek),
+            Some("scotland2007") => Ok(CountMethod::Scotland2007),
+            Some("nsw_lc") => Ok(CountMethod::NswLegislativeCouncil),
+            Some("house") => Ok(CountMethod::House),
+            Some(other) => Err(format!("unknown count method: {}", other)),
+        }
+    }
+
+    /// is the count conducted under the Senate rules?
+    fn is_senate(self) -> bool {
+        matches!(
+            self,
+            CountMethod::Senate
+                | CountMethod::SpecialRecount
+                | CountMethod::DoubleDissolution
+                | CountMethod::SenateGvt
+        )
+    }
+}
+
+fn parse_surplus_method(surplus: &Option<String>) -> Result<SurplusMethod, String> {
+    match surplus.as_ref().map(|s| s.as_str()) {
+        None | Some("unweighted_inclusive_gregory") => {
+            Ok(SurplusMethod::UnweightedInclusiveGregory)
+        }
+        Some("weighted_inclusive_gregory") => Ok(SurplusMethod::WeightedInclusiveGregory),
+        Some("last_parcel") => Ok(SurplusMethod::LastParcel),
+        Some(other) => Err(format!("unknown surplus method: {}", other)),
+    }
+}
+
+fn parse_quota_formula(quota: &Option<String>) -> Result<QuotaFormula, String> {
+    match quota.as_ref().map(|s| s.as_str()) {
+        None | Some("droop") => Ok(QuotaFormula::Droop),
+        Some("hare") => Ok(QuotaFormula::Hare),
+        Some("exact_droop") => Ok(QuotaFormula::ExactDroop),
+        Some("hagenbach_bischoff") => Ok(QuotaFormula::HagenbachBischoff),
+        Some(other) => Err(format!("unknown quota: {}", other)),
+    }
+}
+
+fn parse_bad_lines(bad_lines: &Option<String>) -> Result<BadLines, String> {
+    match bad_lines.as_ref().map(|s| s.as_str()) {
+        None | Some("abort") => Ok(BadLines::Abort),
+        Some("skip") => Ok(BadLines::Skip),
+        Some(other) => Err(format!("unknown bad_lines policy: {}", other)),
+    }
+}
+
+fn parse_rounding_policy(
+    rounding: &Option<String>,
+    places: Option<u32>,
+) -> Result<RoundingPolicy, String> {
+    match (rounding.as_ref().map(|s| s.as_str()), places) {
+        (Some("truncate_transfer_value"), Some(places)) => {
+            Ok(RoundingPolicy::TruncateTransferValue(places))
+        }
+        (Some("truncate_transfer_value"), None) => {
+            Err("truncate_transfer_value requires rounding_places".to_string())
+        }
+        (_, Some(_)) => {
+            Err("rounding_places may only be given for truncate_transfer_value".to_string())
+        }
+        (None, None) | (Some("truncate_votes"), None) => Ok(RoundingPolicy::TruncateVotes),
+        (Some("fractional"), None) => Ok(RoundingPolicy::Fractional),
+        (Some(other), None) => Err(format!("unknown rounding: {}", other)),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CountTask {
     pub description: String,
     pub dataset: String,
     pub state: String,
     pub candidates: String,
+    /// the formal preferences; or, for a count with group voting tickets, the preferences
+    /// of the papers marked below the line
     pub preferences: String,
+    /// for a count with group voting tickets, the tickets lodged by each group
+    pub tickets: Option<String>,
+    /// for a count with group voting tickets, the first preferences by state, which give
+    /// the number of papers marked above the line for each group
+    pub first_preferences: Option<String>,
     pub vacancies: usize,
     pub slug: String,
+    pub method: CountMethod,
+    pub excluded: Vec<String>,
+    pub bulk_exclusion: bool,
+    pub surplus: SurplusMethod,
+    /// for a count under the Senate rules, the formula by which the quota is determined
+    pub quota: QuotaFormula,
+    /// for a count under the Senate rules, the rounding of transfer values and votes
+    pub rounding: RoundingPolicy,
+    /// for a count under Meek's method, the tolerance within which the keep values must
+    /// converge; if not given, `meek::DEFAULT_TOLERANCE`
+    pub tolerance: Option<f64>,
+    /// for a count of the New South Wales Legislative Council, the seed for the random
+    /// selection of papers; if not given, taken from the clock. always recorded in the
+    /// output, so the count can be reproduced
+    pub seed: Option<u64>,
+    /// for a count of the New South Wales Legislative Council, the seeds with which the
+    /// count is repeated, to show how much the random selection affects the outcome
+    pub seeds: Vec<u64>,
+    /// for a count of the House of Representatives, the division
+    pub division: Option<String>,
+    /// for a count followed by a countback to fill a casual vacancy, the candidate vacating
+    /// their seat
+    pub vacating: Option<String>,
+    /// the candidates contesting the casual vacancy
+    pub contesting: Vec<String>,
+    /// what to do with lines of the formal preferences which can't be parsed
+    pub bad_lines: BadLines,
+    pub election_order_ties: Vec<Tie>,
+    pub election_ties: Vec<Tie>,
+    pub exclusion_ties: Vec<Tie>,
+    /// for a count with group voting tickets, the lots drawn to allocate the papers left
+    /// over when a group's papers are divided between its tickets
+    pub ticket_lots: Vec<TicketLot>,
+}
+
+fn find_candidate(candidates: &CandidateData, name: &str) -> Result<CandidateIndex, String> {
+    match candidates.find_by_name(name) {
+        Some(idx) => Ok(idx),
+        None => Err(format!("unknown candidate: {}", name)),
+    }
+}
+
+fn resolve_ties(ties: &[Tie], candidates: &CandidateData) -> Result<Vec<TieResolution>, String> {
+    let lookup = |name: &str| find_candidate(candidates, name);
+    let mut resolutions = Vec::new();
+    for tie in ties {
+        let mut tied = Vec::new();
+        for name in &tie.tie {
+            tied.push(lookup(name)?);
+        }
+        let pick = lookup(&tie.pick)?;
+        if !tied.contains(&pick) {
+            return Err(format!(
+                "tie pick {} is not one of the tied candidates",
+                tie.pick
+            ));
+        }
+        resolutions.push(TieResolution { tie: tied, pick });
+    }
+    Ok(resolutions)
+}
+
+impl CountTask {
+    /// resolve the configured ties against the candidates standing in this count
+    pub fn tie_resolutions(&self, candidates: &CandidateData) -> Result<TieResolutions, String> {
+        Ok(TieResolutions {
+            election_order: resolve_ties(&self.election_order_ties, candidates)?,
+            election: resolve_ties(&self.election_ties, candidates)?,
+            exclusion: resolve_ties(&self.exclusion_ties, candidates)?,
+        })
+    }
+
+    /// the rules under which the count is to be conducted by `CountEngine`; `None` for a
+    /// count under Meek's method, which is conducted by `MeekEngine`, or for a count of the
+    /// House of Representatives, conducted by `IrvEngine`
+    pub fn counting_rules(&self) -> Option<Box<dyn CountingRules>> {
+        match self.method {
+            CountMethod::Senate
+            | CountMethod::SpecialRecount
+            | CountMethod::DoubleDissolution
+            | CountMethod::SenateGvt => Some(Box::new(Senate2016 {
+                bulk_exclusion: self.bulk_exclusion,
+                surplus: self.surplus,
+                quota: self.quota,
+                rounding: self.rounding,
+            })),
+            CountMethod::Scotland2007 => Some(Box::new(Scotland2007)),
+            CountMethod::NswLegislativeCouncil => {
+                Some(Box::new(NswLegislativeCouncil::new(self.seed.unwrap())))
+            }
+            CountMethod::Meek | CountMethod::House => None,
+        }
+    }
+
+    /// resolve the vacating and contesting candidates, for a count followed by a countback
+    pub fn countback_candidates(
+        &self,
+        candidates: &CandidateData,
+    ) -> Result<Option<(CandidateIndex, Vec<CandidateIndex>)>, String> {
+        let vacating = match self.vacating {
+            Some(ref name) => find_candidate(candidates, name)?,
+            None => return Ok(None),
+        };
+        let contesting = self
+            .contesting
+            .iter()
+            .map(|name| find_candidate(candidates, name))
+            .collect::<Result<Vec<CandidateIndex>, String>>()?;
+        Ok(Some((vacating, contesting)))
+    }
+
+    /// resolve the candidates to be treated as excluded from the start of the count
+    pub fn excluded_candidates(
+        &self,
+        candidates: &CandidateData,
+    ) -> Result<Vec<CandidateIndex>, String> {
+        self.excluded
+            .iter()
+            .map(|name| find_candidate(candidates, name))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -83,12 +334,123 @@ pub struct Work {
     pub groups: Vec<CountGroup>,
 }
 
+/// check the configuration of count `slug`, and resolve it into a task
+fn count_task(config: &Config, dir: &Path, slug: &str, count: &Count) -> Result<CountTask, String> {
+    let in_dir = |s: &str| -> String { dir.join(Path::new(s)).to_str().unwrap().to_string() };
+    let dataset = match config.dataset.get(&count.dataset) {
+        Some(d) => d,
+        None => return Err(format!("unknown dataset: {}", count.dataset)),
+    };
+    let method = CountMethod::parse(&count.method)?;
+    if method == CountMethod::DoubleDissolution && count.vacancies % 2 != 0 {
+        return Err("a double_dissolution count must have an even number of vacancies".to_string());
+    }
+    let surplus = parse_surplus_method(&count.surplus)?;
+    if count.surplus.is_some() && !method.is_senate() {
+        return Err("a surplus may only be given for a count under the senate rules".to_string());
+    }
+    if count.bulk_exclusion.is_some() && !method.is_senate() {
+        return Err(
+            "bulk_exclusion may only be given for a count under the senate rules".to_string(),
+        );
+    }
+    let quota = parse_quota_formula(&count.quota)?;
+    if count.quota.is_some() && !method.is_senate() {
+        return Err("a quota may only be given for a count under the senate rules".to_string());
+    }
+    let rounding = parse_rounding_policy(&count.rounding, count.rounding_places)?;
+    if count.rounding.is_some() && !method.is_senate() {
+        return Err("a rounding may only be given for a count under the senate rules".to_string());
+    }
+    if method == CountMethod::SenateGvt
+        && (dataset.tickets.is_none() || dataset.first_preferences.is_none())
+    {
+        return Err(
+            "a senate_gvt count requires tickets and first_preferences in its dataset".to_string(),
+        );
+    }
+    if count.tolerance.is_some() && method != CountMethod::Meek {
+        return Err("a tolerance may only be given for a meek count".to_string());
+    }
+    if count.seed.is_some() && method != CountMethod::NswLegislativeCouncil {
+        return Err("a seed may only be given for a nsw_lc count".to_string());
+    }
+    let seeds = count.seeds.clone().unwrap_or_default();
+    if !seeds.is_empty() && method != CountMethod::NswLegislativeCouncil {
+        return Err("seeds may only be given for a nsw_lc count".to_string());
+    }
+    let seed = match method {
+        CountMethod::NswLegislativeCouncil => Some(count.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64
+        })),
+        _ => None,
+    };
+    if method == CountMethod::House && (count.division.is_none() || count.vacancies != 1) {
+        return Err("a house count requires a division, and has a single vacancy".to_string());
+    }
+    let contesting = count.contesting.clone().unwrap_or_default();
+    if count.vacating.is_some() != !contesting.is_empty() {
+        return Err("a countback requires both the vacating and contesting candidates".to_string());
+    }
+    if count.vacating.is_some() && !method.is_senate() {
+        return Err("a countback may only follow a count under the senate rules".to_string());
+    }
+    if count.vacating.is_some() && surplus != SurplusMethod::LastParcel {
+        return Err(
+            "a countback may only follow a count with surplus = \"last_parcel\"".to_string(),
+        );
+    }
+    if count
+        .vacating
+        .as_ref()
+        .map_or(false, |name| contesting.contains(name))
+    {
+        return Err("the vacating candidate may not contest the vacancy".to_string());
+    }
+    let bad_lines = parse_bad_lines(&count.bad_lines)?;
+    let in_data = |s: &str| in_dir(&format!("{}/data/{}", slug, s));
+    let excluded = count.excluded.clone().unwrap_or_default();
+    if !excluded.is_empty() && method != CountMethod::SpecialRecount {
+        return Err("excluded candidates may only be given for a special_recount".to_string());
+    }
+    Ok(CountTask {
+        state: slug.to_string(),
+        slug: slug.to_string(),
+        description: count.description.clone(),
+        dataset: count.dataset.clone(),
+        candidates: in_dir(&config.candidates.all),
+        preferences: in_data(&dataset.preferences),
+        tickets: dataset.tickets.as_ref().map(|s| in_data(s)),
+        first_preferences: dataset.first_preferences.as_ref().map(|s| in_data(s)),
+        vacancies: count.vacancies,
+        method,
+        excluded,
+        bulk_exclusion: count.bulk_exclusion.unwrap_or(false),
+        surplus,
+        quota,
+        rounding,
+        tolerance: count.tolerance,
+        seed,
+        seeds,
+        division: count.division.clone(),
+        vacating: count.vacating.clone(),
+        contesting,
+        bad_lines,
+        election_order_ties: count.election_order_ties.clone(),
+        election_ties: count.election_ties.clone(),
+        exclusion_ties: count.exclusion_ties.clone(),
+        ticket_lots: count.ticket_lots.clone().unwrap_or_default(),
+    })
+}
+
 pub fn read_config(input_files: Vec<&str>) -> Work {
     let mut work = Work { groups: Vec::new() };
     for fname in input_files {
         let path = Path::new(fname);
         let dir = path.parent().unwrap().canonicalize().unwrap();
-        let in_dir = |s: &str| -> String { dir.join(Path::new(s)).to_str().unwrap().to_string() };
         let config = match config_contents(fname) {
             Ok(c) => c,
             Err(e) => {
@@ -99,23 +461,15 @@ pub fn read_config(input_files: Vec<&str>) -> Work {
         let counts = config
             .count
             .iter()
-            .map(|(slug, count)| {
-                let dataset = match config.dataset.get(&count.dataset) {
-                    Some(d) => d,
-                    None => {
-                        panic!();
+            .filter_map(
+                |(slug, count)| match count_task(&config, &dir, slug, count) {
+                    Ok(task) => Some(task),
+                    Err(e) => {
+                        println!("{}: {}: {}", fname, slug, e);
+                        None
                     }
-                };
-                CountTask {
-                    state: slug.clone(),
-                    slug: slug.clone(),
-                    description: count.description.clone(),
-                    dataset: count.dataset.clone(),
-                    candidates: in_dir(&config.candidates.all).clone(),
-                    preferences: in_dir(&format!("{}/data/{}", slug, dataset.preferences)),
-                    vacancies: count.vacancies,
-                }
-            })
+                },
+            )
             .collect();
         work.groups.push(CountGroup {
             filename: path.file_name().unwrap().to_string_lossy().to_string(),
@@ -0,0 +1,307 @@
+/*
+ * the filling of a casual vacancy in a Hare-Clark election, by a countback of the papers
+ * which elected the vacating member
+ */
+
+use defs::*;
+use engine::{
+    ascending_by_votes, lowest_candidate, print_candidate_totals, CountError, CountOutcome,
+    CountState, Counter, TieBreaking,
+};
+use num::rational::BigRational;
+use num::Zero;
+use rules::{total, whole};
+use std::collections::HashMap;
+
+/// a countback to fill a casual vacancy, as for the Tasmanian House of Assembly under the
+/// Electoral Act 2004. the papers which gave the vacating member their quota, each at the
+/// value at which it was kept by the member, are allotted to the contesting candidate next
+/// in order of preference after the vacating member. a candidate
+/// with an absolute majority of the votes not exhausted is elected; otherwise the candidate
+/// with the fewest votes is excluded, and their papers passed on at the same value
+pub struct CountbackEngine {
+    total_papers: u32,
+    /// half of the votes not exhausted at the last count, which a candidate must exceed to
+    /// be elected
+    quota: BigRational,
+    candidates: CandidateData,
+    /// the papers held by each continuing contesting candidate
+    papers: HashMap<CandidateIndex, Vec<BundleTransaction>>,
+    /// the candidates which papers skip over: those not contesting the vacancy, and the
+    /// contesting candidates excluded
+    inactive: CountResults,
+    elected: Vec<CandidateIndex>,
+    excluded: Vec<CandidateIndex>,
+    /// the candidate excluded at the last count, whose papers are yet to be distributed
+    pending_exclusion: Option<CandidateIndex>,
+    votes_exhausted: BigRational,
+    papers_exhausted: u32,
+    count_states: Vec<CountState>,
+    tie_breaking: TieBreaking,
+}
+
+impl CountbackEngine {
+    /// Create a countback for the vacancy left by `vacating`, who was elected with
+    /// `quota_papers`, among the `contesting` candidates
+    pub fn new(
+        candidates: CandidateData,
+        vacating: CandidateIndex,
+        quota_papers: Vec<BundleTransaction>,
+        contesting: &[CandidateIndex],
+        ties: TieResolutions,
+    ) -> CountbackEngine {
+        let mut inactive = CountResults::new();
+        for idx in 0..candidates.count {
+            let candidate = CandidateIndex(idx as u16);
+            if candidate == vacating || !contesting.contains(&candidate) {
+                inactive.candidate_excluded(candidate);
+            }
+        }
+        let mut engine = CountbackEngine {
+            total_papers: quota_papers.iter().map(|bt| bt.papers).sum(),
+            quota: BigRational::zero(),
+            candidates,
+            papers: contesting.iter().map(|c| (*c, Vec::new())).collect(),
+            inactive,
+            elected: Vec::new(),
+            excluded: Vec::new(),
+            pending_exclusion: None,
+            votes_exhausted: BigRational::zero(),
+            papers_exhausted: 0,
+            count_states: Vec::new(),
+            tie_breaking: TieBreaking::new(ties),
+        };
+        engine.allot(quota_papers);
+        engine
+    }
+
+    /// pass each paper on to the next contesting candidate in order of preference, at the
+    /// value at which it is held. returns the votes exhausted
+    fn allot(&mut self, bundle_transactions: Vec<BundleTransaction>) -> BigRational {
+        let count = self.count_states.len() + 1;
+        let mut votes_exhausted = BigRational::zero();
+        for bundle_transaction in bundle_transactions {
+            let transfer_value = bundle_transaction.transfer_value;
+            let mut by_candidate: HashMap<CandidateIndex, Vec<BallotState>> = HashMap::new();
+            for mut ballot_state in bundle_transaction.ballot_states {
+                ballot_state.goto_next_preference(&self.inactive);
+                match ballot_state.current_preference() {
+                    Some(candidate) => by_candidate
+                        .entry(candidate)
+                        .or_insert_with(Vec::new)
+                        .push(ballot_state),
+                    None => {
+                        votes_exhausted =
+                            votes_exhausted + &transfer_value * whole(ballot_state.count);
+                        self.papers_exhausted += ballot_state.count;
+                    }
+                }
+            }
+            for (candidate, ballot_states) in by_candidate {
+                let papers = ballot_states.iter().map(|bs| bs.count).sum();
+                self.papers
+                    .get_mut(&candidate)
+                    .unwrap()
+                    .push(BundleTransaction {
+                        ballot_states,
+                        transfer_value: transfer_value.clone(),
+                        votes: &transfer_value * whole(papers),
+                        papers,
+                        count,
+                    });
+            }
+        }
+        self.votes_exhausted = &self.votes_exhausted + &votes_exhausted;
+        votes_exhausted
+    }
+
+    fn votes(&self, candidate: CandidateIndex) -> BigRational {
+        total(self.papers[&candidate].iter().map(|bt| &bt.votes))
+    }
+
+    fn build_count_state(
+        &self,
+        votes_exhausted_in_count: BigRational,
+        papers_before: u32,
+    ) -> CountState {
+        let mut vpc = HashMap::new();
+        let mut ppc = HashMap::new();
+        for (candidate, bundle_transactions) in self.papers.iter() {
+            vpc.insert(*candidate, self.votes(*candidate));
+            ppc.insert(
+                *candidate,
+                bundle_transactions.iter().map(|bt| bt.papers).sum(),
+            );
+        }
+        CountState {
+            votes_per_candidate: vpc,
+            papers_per_candidate: ppc,
+            votes_exhausted: self.votes_exhausted.clone(),
+            papers_exhausted: self.papers_exhausted,
+            votes_exhausted_in_count,
+            papers_exhausted_in_count: self.papers_exhausted - papers_before,
+            votes_lost_by_fraction: BigRational::zero(),
+            votes_lost_by_fraction_in_count: BigRational::zero(),
+        }
+    }
+
+    /// the continuing candidates, in ascending order of votes
+    fn continuing_candidates(&self) -> Vec<CandidateIndex> {
+        ascending_by_votes(self.papers.keys().cloned(), |c| self.votes(c))
+    }
+
+    fn exclude_a_candidate(&mut self, continuing: &[CandidateIndex]) -> Result<(), CountError> {
+        let to_exclude = lowest_candidate(
+            continuing,
+            &self.count_states,
+            false,
+            &mut self.tie_breaking,
+            &self.candidates,
+        )?;
+        self.excluded.push(to_exclude);
+        self.inactive.candidate_excluded(to_exclude);
+        self.pending_exclusion = Some(to_exclude);
+        Ok(())
+    }
+}
+
+impl Counter for CountbackEngine {
+    fn vacancies(&self) -> u32 {
+        1
+    }
+
+    fn total_papers(&self) -> u32 {
+        self.total_papers
+    }
+
+    fn quota(&self) -> &BigRational {
+        &self.quota
+    }
+
+    fn rules_name(&self) -> &'static str {
+        "countback"
+    }
+
+    fn count(&mut self) -> Result<CountOutcome, CountError> {
+        let (votes_exhausted_in_count, papers_before) = match self.pending_exclusion.take() {
+            Some(candidate) => {
+                let papers_before = self.papers_exhausted;
+                let bundle_transactions = self.papers.remove(&candidate).unwrap();
+                (self.allot(bundle_transactions), papers_before)
+            }
+            // the papers were allotted as the countback was created
+            None => (self.votes_exhausted.clone(), 0),
+        };
+        let count_state = self.build_count_state(votes_exhausted_in_count, papers_before);
+        self.count_states.push(count_state.clone());
+        let number = self.count_states.len();
+
+        let continuing = self.continuing_candidates();
+        self.quota = total(count_state.votes_per_candidate.values()) / whole(2);
+        if let Some(leader) = continuing.last() {
+            if count_state.votes_per_candidate[leader] > self.quota {
+                self.elected.push(*leader);
+                return Ok(CountOutcome::CountComplete(number, count_state));
+            }
+        }
+        if continuing.len() <= 2 {
+            // with two candidates remaining, neither has a majority only if they are tied
+            let winner = match continuing.len() {
+                2 => self.tie_breaking.resolve(
+                    number,
+                    TieKind::Election,
+                    &continuing,
+                    &self.candidates,
+                )?,
+                _ => continuing[0],
+            };
+            self.elected.push(winner);
+            return Ok(CountOutcome::CountComplete(number, count_state));
+        }
+        self.exclude_a_candidate(&continuing)?;
+        Ok(CountOutcome::CountContinues(number, count_state))
+    }
+
+    fn get_elected(&self) -> &Vec<CandidateIndex> {
+        &self.elected
+    }
+
+    fn get_excluded(&self) -> &Vec<CandidateIndex> {
+        &self.excluded
+    }
+
+    fn get_tie_breaks(&self) -> &Vec<TieBreak> {
+        self.tie_breaking.get_tie_breaks()
+    }
+
+    fn set_tie_decision(&mut self, tie_decision: TieDecision) {
+        self.tie_breaking.set_tie_decision(tie_decision);
+    }
+
+    fn print_debug(&self) {
+        println!(
+            "-- CountbackEngine::print_debug (count {}) --",
+            self.count_states.len()
+        );
+        println!("Candidates: {}", self.candidates.count);
+        println!("Quota papers: {}", self.total_papers);
+        println!("Half of the votes not exhausted: {}", self.quota);
+        print_candidate_totals(
+            &self.candidates,
+            &self.continuing_candidates(),
+            |c| self.votes(c),
+            &self.elected,
+            &self.excluded,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{ballot_state, bundle_transaction, candidate_data};
+
+    #[test]
+    fn test_countback() {
+        // A vacates; E is not contesting, so the papers for E pass on to D. C is excluded,
+        // and their papers elect B
+        let quota_papers = vec![
+            bundle_transaction(
+                vec![
+                    ballot_state(&[0, 1, 2], 3),
+                    ballot_state(&[0, 2, 1], 2),
+                    ballot_state(&[0, 4, 3], 2),
+                    ballot_state(&[0, 4], 1),
+                ],
+                whole(1),
+                1,
+            ),
+            bundle_transaction(vec![ballot_state(&[0, 3], 4)], whole(1) / whole(4), 1),
+        ];
+        let mut engine = CountbackEngine::new(
+            candidate_data(&["A", "B", "C", "D", "E"]),
+            CandidateIndex(0),
+            quota_papers,
+            &[CandidateIndex(1), CandidateIndex(2), CandidateIndex(3)],
+            TieResolutions::new(),
+        );
+        assert_eq!(engine.total_papers, 12);
+        let first = match engine.count().unwrap() {
+            CountOutcome::CountContinues(_, state) => state,
+            CountOutcome::CountComplete(_, _) => panic!("countback completed at first count"),
+        };
+        assert_eq!(first.votes_per_candidate[&CandidateIndex(1)], whole(3));
+        assert_eq!(first.votes_per_candidate[&CandidateIndex(2)], whole(2));
+        assert_eq!(first.votes_per_candidate[&CandidateIndex(3)], whole(3));
+        assert_eq!(first.votes_exhausted, whole(1));
+        assert_eq!(engine.get_excluded(), &vec![CandidateIndex(2)]);
+        match engine.count().unwrap() {
+            CountOutcome::CountComplete(2, state) => {
+                assert_eq!(state.votes_per_candidate[&CandidateIndex(1)], whole(5));
+            }
+            _ => panic!("countback not completed at second count"),
+        }
+        assert_eq!(engine.quota, whole(4));
+        assert_eq!(engine.get_elected(), &vec![CandidateIndex(1)]);
+    }
+}
@@ -4,22 +4,95 @@
 
 use num::rational::BigRational;
 use std::collections::HashSet;
+use std::fmt;
+use std::iter::FromIterator;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 /// candidate's index on the ballot paper
 /// ranges from `0..N-1` where `N` is the number of candidates
-pub struct CandidateIndex(pub u8);
+pub struct CandidateIndex(pub u16);
 
 /// group's index on the ballot paper
 /// ranges from `0..N-1` where N is the number of groups
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-pub struct GroupIndex(pub u8);
+pub struct GroupIndex(pub u16);
 
-#[derive(Debug)]
+/// the most candidates, or groups, which can be on a ballot paper; every preference, from
+/// one up to the number of candidates, must fit in a `u16`
+pub const MAX_CANDIDATES: usize = u16::MAX as usize;
+
+/// an error, rather than indices which wrap, if there are more than `MAX_CANDIDATES`
+/// candidates
+pub fn check_candidate_count(candidates: usize) -> Result<(), String> {
+    if candidates > MAX_CANDIDATES {
+        return Err(format!(
+            "{} candidates: at most {} are supported",
+            candidates, MAX_CANDIDATES
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+/// the candidates on a paper, in order of preference. held with a byte for each candidate
+/// where every index fits, as it does for any Senate election to date, so that the millions
+/// of papers in a count take no more memory than they need
+pub enum Form {
+    Narrow(Box<[u8]>),
+    Wide(Box<[u16]>),
+}
+
+impl Form {
+    pub fn len(&self) -> usize {
+        match self {
+            Form::Narrow(form) => form.len(),
+            Form::Wide(form) => form.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the candidate given preference `idx + 1`
+    pub fn get(&self, idx: usize) -> Option<CandidateIndex> {
+        match self {
+            Form::Narrow(form) => form.get(idx).map(|c| CandidateIndex(u16::from(*c))),
+            Form::Wide(form) => form.get(idx).map(|c| CandidateIndex(*c)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CandidateIndex> + '_ {
+        (0..self.len()).map(move |idx| self.get(idx).unwrap())
+    }
+
+    pub fn to_vec(&self) -> Vec<CandidateIndex> {
+        self.iter().collect()
+    }
+}
+
+impl FromIterator<CandidateIndex> for Form {
+    fn from_iter<I: IntoIterator<Item = CandidateIndex>>(iter: I) -> Form {
+        let form: Vec<u16> = iter.into_iter().map(|c| c.0).collect();
+        if form.iter().all(|c| *c <= u16::from(u8::MAX)) {
+            Form::Narrow(form.into_iter().map(|c| c as u8).collect())
+        } else {
+            Form::Wide(form.into_boxed_slice())
+        }
+    }
+}
+
+impl fmt::Debug for Form {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Debug, Clone)]
 /// `count` ballots in the count, all with the same `form`,
 /// expressing `active_preference`
 pub struct BallotState {
-    pub form: Vec<CandidateIndex>,
+    pub form: Form,
     pub count: u32,
     pub active_preference: usize,
 }
@@ -73,11 +146,16 @@ impl BallotState {
     }
 
     pub fn current_preference(&self) -> Option<CandidateIndex> {
-        if self.alive() {
-            Some(self.form[self.active_preference])
-        } else {
-            None
-        }
+        self.form.get(self.active_preference)
+    }
+
+    /// is there a later preference for a candidate who is neither elected nor excluded, to
+    /// which the paper could be transferred?
+    pub fn has_next_preference(&self, results: &CountResults) -> bool {
+        self.form
+            .iter()
+            .skip(self.active_preference + 1)
+            .any(|candidate| !results.candidate_is_inactive(candidate))
     }
 
     pub fn goto_next_preference(&mut self, results: &CountResults) {
@@ -99,18 +177,21 @@ impl BallotState {
 
 /// a collection of ballot states, all of which were transferred to
 /// the total of a candidate during a count. the member `votes`
-/// represents the integer value of the votes transferred to the
-/// candidate, after the application of the transfer value to the
-/// total number of papers in the transaction
-#[derive(Debug)]
+/// represents the value of the votes transferred to the candidate,
+/// after the application of the transfer value to the total number
+/// of papers in the transaction; under the Senate rules, this is a
+/// whole number
+#[derive(Debug, Clone)]
 pub struct BundleTransaction {
     pub ballot_states: Vec<BallotState>,
     pub transfer_value: BigRational,
-    pub votes: u32,
+    pub votes: BigRational,
     pub papers: u32,
+    /// the count at which the papers were received by the candidate
+    pub count: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CandidateData {
     pub count: usize,
     pub names: Vec<String>,
@@ -132,4 +213,125 @@ impl CandidateData {
     pub fn get_party(&self, idx: CandidateIndex) -> String {
         self.parties[idx.0 as usize].clone()
     }
+    pub fn find_by_name(&self, name: &str) -> Option<CandidateIndex> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .map(|idx| CandidateIndex(idx as u16))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// the kinds of tie which can arise during a count, and which may
+/// need to be resolved by the Australian Electoral Officer
+pub enum TieKind {
+    /// candidates elected in the same count with the same number of votes
+    ElectionOrder,
+    /// the last two continuing candidates for the last vacancy, section 273(17)
+    Election,
+    /// candidates with the fewest votes, one of whom must be excluded
+    Exclusion,
+}
+
+#[derive(Debug, Clone)]
+/// a resolution of a tie between `tie` candidates, in favour of `pick`.
+/// for an exclusion tie `pick` is excluded; otherwise `pick` is elected
+/// (or elected first, for an election order tie)
+pub struct TieResolution {
+    pub tie: Vec<CandidateIndex>,
+    pub pick: CandidateIndex,
+}
+
+#[derive(Debug, Clone, Default)]
+/// tie resolutions supplied to the count, by kind of tie
+pub struct TieResolutions {
+    pub election_order: Vec<TieResolution>,
+    pub election: Vec<TieResolution>,
+    pub exclusion: Vec<TieResolution>,
+}
+
+impl TieResolutions {
+    pub fn new() -> TieResolutions {
+        TieResolutions {
+            election_order: Vec::new(),
+            election: Vec::new(),
+            exclusion: Vec::new(),
+        }
+    }
+
+    /// find the resolution for a tie between exactly `candidates`, if one has been supplied
+    pub fn resolve(&self, kind: TieKind, candidates: &[CandidateIndex]) -> Option<CandidateIndex> {
+        let resolutions = match kind {
+            TieKind::ElectionOrder => &self.election_order,
+            TieKind::Election => &self.election,
+            TieKind::Exclusion => &self.exclusion,
+        };
+        let wanted: HashSet<&CandidateIndex> = candidates.iter().collect();
+        resolutions
+            .iter()
+            .find(|r| r.tie.len() == wanted.len() && r.tie.iter().all(|c| wanted.contains(c)))
+            .map(|r| r.pick)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// how a tie was broken
+pub enum TieBreakMethod {
+    /// by reference to the most recent count at which the candidates' votes differed
+    PreviousCount,
+    /// by a tie resolution supplied with the count
+    Resolution,
+    /// by a decision made while the count was in progress
+    Decision,
+}
+
+#[derive(Debug, Clone)]
+/// a record of a tie broken during a count
+pub struct TieBreak {
+    pub count: usize,
+    pub kind: TieKind,
+    pub candidates: Vec<CandidateIndex>,
+    pub pick: CandidateIndex,
+    pub method: TieBreakMethod,
+}
+
+/// called upon to decide a tie which can't otherwise be broken, on behalf of the
+/// Australian Electoral Officer. returns the candidate picked, as for `TieResolution`
+pub type TieDecision = Box<dyn FnMut(TieKind, &[CandidateIndex]) -> Option<CandidateIndex>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem;
+
+    #[test]
+    fn form_width() {
+        let narrow: Form = [2, 0, 255].iter().map(|c| CandidateIndex(*c)).collect();
+        match narrow {
+            Form::Narrow(_) => {}
+            Form::Wide(_) => panic!("form should be narrow"),
+        }
+        assert_eq!(
+            narrow.to_vec(),
+            vec![CandidateIndex(2), CandidateIndex(0), CandidateIndex(255)]
+        );
+        let wide: Form = [2, 300].iter().map(|c| CandidateIndex(*c)).collect();
+        match wide {
+            Form::Narrow(_) => panic!("form should be wide"),
+            Form::Wide(_) => {}
+        }
+        assert_eq!(wide.get(1), Some(CandidateIndex(300)));
+        assert_eq!(wide.get(2), None);
+        // no larger than the vector of indices it replaces
+        assert!(mem::size_of::<Form>() <= mem::size_of::<Vec<u8>>());
+    }
+
+    #[test]
+    fn candidate_count() {
+        assert!(check_candidate_count(MAX_CANDIDATES).is_ok());
+        assert_eq!(
+            check_candidate_count(MAX_CANDIDATES + 1),
+            Err("65536 candidates: at most 65535 are supported".to_string())
+        );
+    }
 }
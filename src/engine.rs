@@ -1,9 +1,10 @@
 use defs::*;
-use num::rational::Ratio;
-use num::BigInt;
-use num::{FromPrimitive, ToPrimitive};
+use num::rational::{BigRational, Ratio};
+use num::{BigInt, Zero};
 use rayon::prelude::*;
+use rules::*;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 
 #[derive(Debug)]
 /// the outcome of a count
@@ -14,12 +15,65 @@ pub enum CountOutcome {
     CountContinues(usize, CountState),
 }
 
+#[derive(Debug)]
+/// an error which prevents the count from proceeding
+pub enum CountError {
+    /// a tie which could not be broken by reference to previous counts, and
+    /// for which no resolution was supplied
+    UnresolvedTie(TieKind, Vec<String>),
+    /// the votes accounted for at the end of a count (held by candidates, exhausted,
+    /// and lost by fraction) don't add up to the total number of papers
+    VotesNotConserved {
+        count: usize,
+        accounted: BigRational,
+        total_papers: u32,
+    },
+    /// an iterative count did not converge: after `iterations`, the total of the elected
+    /// candidates' surpluses was still `surplus` votes, more than the tolerance
+    NotConverged {
+        iterations: usize,
+        surplus: BigRational,
+    },
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CountError::UnresolvedTie(kind, names) => write!(
+                f,
+                "no resolution supplied for {:?} tie between: {}",
+                kind,
+                names.join("; ")
+            ),
+            CountError::VotesNotConserved {
+                count,
+                accounted,
+                total_papers,
+            } => write!(
+                f,
+                "votes not conserved at count {}: {} votes accounted for, {} papers in count",
+                count, accounted, total_papers
+            ),
+            CountError::NotConverged {
+                iterations,
+                surplus,
+            } => write!(
+                f,
+                "not converged after {} iterations: surplus of {} votes remains",
+                iterations, surplus
+            ),
+        }
+    }
+}
+
 // these actions are in precedence order, low-to-high
 #[derive(Debug)]
 enum CountAction {
     FirstCount,
-    ExclusionDistribution(CandidateIndex, Ratio<BigInt>),
-    ElectionDistribution(CandidateIndex, Ratio<BigInt>),
+    /// the papers of the excluded candidates received at the given transfer value; or, if
+    /// there is none, all of their papers, each at the value at which it was received
+    ExclusionDistribution(Vec<CandidateIndex>, Option<Ratio<BigInt>>),
+    ElectionDistribution(CandidateIndex, BigRational),
 }
 
 #[derive(Debug, Clone)]
@@ -27,31 +81,288 @@ enum CountAction {
 /// referred to when breaking ties for candidate election or
 /// exclusion
 pub struct CountState {
-    pub votes_per_candidate: HashMap<CandidateIndex, u32>,
+    pub votes_per_candidate: HashMap<CandidateIndex, BigRational>,
     pub papers_per_candidate: HashMap<CandidateIndex, u32>,
-    pub votes_exhausted: u32,
+    /// total votes exhausted, up to and including this count
+    pub votes_exhausted: BigRational,
+    /// total papers exhausted, up to and including this count
     pub papers_exhausted: u32,
+    /// votes exhausted by the distribution in this count
+    pub votes_exhausted_in_count: BigRational,
+    /// papers exhausted by the distribution in this count
+    pub papers_exhausted_in_count: u32,
+    /// total votes lost by fraction, up to and including this count
+    pub votes_lost_by_fraction: BigRational,
+    /// votes lost by fraction in the distribution in this count
+    pub votes_lost_by_fraction_in_count: BigRational,
 }
 
-#[derive(Debug)]
-/// the number of papers and votes exhausted as the result of a distribution
+/// a count in progress, conducted one count at a time. implemented by `CountEngine`, and by
+/// counts under other methods of STV (such as `MeekEngine`) which report their progress in
+/// the same way
+pub trait Counter {
+    /// the number of vacancies to be filled
+    fn vacancies(&self) -> u32;
+
+    /// the total number of papers in the count
+    fn total_papers(&self) -> u32;
+
+    /// the quota for election; for a count where the quota varies, the quota at the
+    /// start of the count
+    fn quota(&self) -> &BigRational;
+
+    /// the name of the rules under which the count is being conducted
+    fn rules_name(&self) -> &'static str;
+
+    /// count votes, once (a single count)
+    fn count(&mut self) -> Result<CountOutcome, CountError>;
+
+    /// the candidates elected so far in the count, in order of election
+    fn get_elected(&self) -> &Vec<CandidateIndex>;
+
+    /// the candidates excluded so far in the count, in order of exclusion
+    fn get_excluded(&self) -> &Vec<CandidateIndex>;
+
+    /// the ties broken so far in the count
+    fn get_tie_breaks(&self) -> &Vec<TieBreak>;
+
+    /// set the decision-maker called upon for ties which can't be broken by reference to
+    /// previous counts, and for which no resolution was supplied
+    fn set_tie_decision(&mut self, tie_decision: TieDecision);
+
+    /// the papers which gave an elected candidate their quota, each bundle at the value
+    /// kept by the candidate; `None` unless the engine was asked to retain them
+    fn quota_papers(&self, _candidate: CandidateIndex) -> Option<Vec<BundleTransaction>> {
+        None
+    }
+
+    fn print_debug(&self);
+}
+
+#[derive(Debug, Clone)]
+/// the number of papers and votes exhausted as the result of a distribution, and
+/// the number of votes lost due to the truncation of fractional votes
 struct DistributionOutcome {
-    votes_exhausted: u32,
+    votes_exhausted: BigRational,
     papers_exhausted: u32,
+    votes_lost_by_fraction: BigRational,
+}
+
+impl DistributionOutcome {
+    fn new() -> DistributionOutcome {
+        DistributionOutcome {
+            votes_exhausted: BigRational::zero(),
+            papers_exhausted: 0,
+            votes_lost_by_fraction: BigRational::zero(),
+        }
+    }
 }
 
 /// Single Transferable Vote count engine
 pub struct CountEngine {
     pub vacancies: u32,
     pub total_papers: u32,
-    pub quota: u32,
+    pub quota: BigRational,
+    rules: Box<dyn CountingRules>,
     candidates: CandidateData,
     /// the `BundleTransaction`s held by each candidate
     candidate_bundle_transactions: HashMap<CandidateIndex, CandidateBundleTransactions>,
     count_states: Vec<CountState>,
     results: CountResults,
     actions_pending: VecDeque<CountAction>,
-    automation: VecDeque<usize>,
+    tie_breaking: TieBreaking,
+    /// papers and votes exhausted, and votes lost by fraction, over the course of the count
+    distributed: DistributionOutcome,
+    /// votes kept by elected candidates whose surplus has been distributed
+    votes_retained: BigRational,
+    /// if retained, the papers kept by each elected candidate whose surplus has been
+    /// distributed
+    quota_papers: Option<HashMap<CandidateIndex, Vec<BundleTransaction>>>,
+}
+
+/// breaks ties which can't be broken by reference to previous counts, using the supplied
+/// tie resolutions or failing that the tie decision; and records the ties broken in a count
+pub struct TieBreaking {
+    ties: TieResolutions,
+    tie_decision: Option<TieDecision>,
+    tie_breaks: Vec<TieBreak>,
+}
+
+impl TieBreaking {
+    pub fn new(ties: TieResolutions) -> TieBreaking {
+        TieBreaking {
+            ties,
+            tie_decision: None,
+            tie_breaks: Vec::new(),
+        }
+    }
+
+    pub fn set_tie_decision(&mut self, tie_decision: TieDecision) {
+        self.tie_decision = Some(tie_decision);
+    }
+
+    pub fn get_tie_breaks(&self) -> &Vec<TieBreak> {
+        &self.tie_breaks
+    }
+
+    /// record a tie broken at `count`
+    pub fn record(
+        &mut self,
+        count: usize,
+        kind: TieKind,
+        candidates: &[CandidateIndex],
+        pick: CandidateIndex,
+        method: TieBreakMethod,
+    ) {
+        self.tie_breaks.push(TieBreak {
+            count,
+            kind,
+            candidates: candidates.to_vec(),
+            pick,
+            method,
+        });
+    }
+
+    /// resolve a tie at `count` using the supplied tie resolutions, or failing that the tie decision
+    pub fn resolve(
+        &mut self,
+        count: usize,
+        kind: TieKind,
+        candidates: &[CandidateIndex],
+        candidate_data: &CandidateData,
+    ) -> Result<CandidateIndex, CountError> {
+        if let Some(pick) = self.ties.resolve(kind, candidates) {
+            self.record(count, kind, candidates, pick, TieBreakMethod::Resolution);
+            return Ok(pick);
+        }
+        let decided = match self.tie_decision {
+            Some(ref mut decide) => decide(kind, candidates),
+            None => None,
+        };
+        match decided {
+            Some(pick) if candidates.contains(&pick) => {
+                self.record(count, kind, candidates, pick, TieBreakMethod::Decision);
+                Ok(pick)
+            }
+            _ => Err(CountError::UnresolvedTie(
+                kind,
+                candidates
+                    .iter()
+                    .map(|c| candidate_data.get_name(*c))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// look through the counts before the most recent, looking for a count where the votes of each
+/// of the candidates are distinct: working back from the most recent count or, if `earliest`,
+/// forward from the first count. if found, returns the candidates in ascending vote order
+pub fn find_tie_breaker(
+    count_states: &[CountState],
+    candidates: &[CandidateIndex],
+    earliest: bool,
+) -> Option<Vec<CandidateIndex>> {
+    let previous = &count_states[..count_states.len().saturating_sub(1)];
+    let search: Box<dyn Iterator<Item = &CountState>> = if earliest {
+        Box::new(previous.iter())
+    } else {
+        Box::new(previous.iter().rev())
+    };
+    for count_state in search {
+        let mut candidate_votes = Vec::new();
+        let mut vote_set = HashSet::new();
+        for candidate in candidates {
+            // a candidate who has never held a bundle has no entry in the count state
+            let votes = count_state
+                .votes_per_candidate
+                .get(candidate)
+                .cloned()
+                .unwrap_or_else(BigRational::zero);
+            vote_set.insert(votes.clone());
+            candidate_votes.push((*candidate, votes));
+        }
+        if vote_set.len() == candidates.len() {
+            candidate_votes.sort_by(|a, b| a.1.cmp(&b.1));
+            return Some(candidate_votes.drain(..).map(|(c, _)| c).collect());
+        }
+    }
+    None
+}
+
+/// the `continuing` candidates in a count which excludes one candidate at a time, in ascending
+/// order of their `votes`; candidates with equal votes are in ballot paper order
+pub fn ascending_by_votes<V: Ord>(
+    continuing: impl Iterator<Item = CandidateIndex>,
+    votes: impl Fn(CandidateIndex) -> V,
+) -> Vec<CandidateIndex> {
+    let mut continuing: Vec<CandidateIndex> = continuing.collect();
+    continuing.sort_by_key(|c| (votes(*c), *c));
+    continuing
+}
+
+/// the candidate to be excluded: of the `continuing` candidates, in ascending order of their
+/// votes at the latest of `count_states`, the candidate with the fewest votes. a tie is broken
+/// by reference to previous counts (the `earliest` count at which the votes differed, or the
+/// most recent) or, failing that, by `tie_breaking`
+pub fn lowest_candidate(
+    continuing: &[CandidateIndex],
+    count_states: &[CountState],
+    earliest: bool,
+    tie_breaking: &mut TieBreaking,
+    candidates: &CandidateData,
+) -> Result<CandidateIndex, CountError> {
+    let votes = &count_states[count_states.len() - 1].votes_per_candidate;
+    let min_votes = &votes[&continuing[0]];
+    let exclusion_candidates: Vec<CandidateIndex> = continuing
+        .iter()
+        .filter(|c| votes[c] == *min_votes)
+        .cloned()
+        .collect();
+    if exclusion_candidates.len() == 1 {
+        return Ok(exclusion_candidates[0]);
+    }
+    match find_tie_breaker(count_states, &exclusion_candidates, earliest) {
+        Some(tie_broken_candidates) => {
+            let pick = tie_broken_candidates[0];
+            tie_breaking.record(
+                count_states.len(),
+                TieKind::Exclusion,
+                &exclusion_candidates,
+                pick,
+                TieBreakMethod::PreviousCount,
+            );
+            Ok(pick)
+        }
+        None => tie_breaking.resolve(
+            count_states.len(),
+            TieKind::Exclusion,
+            &exclusion_candidates,
+            candidates,
+        ),
+    }
+}
+
+/// print the votes of the `continuing` candidates, given in ascending order of votes, and
+/// the candidates elected and excluded; for the debug output of a count for a single vacancy
+pub fn print_candidate_totals<V: fmt::Display>(
+    candidates: &CandidateData,
+    continuing: &[CandidateIndex],
+    votes: impl Fn(CandidateIndex) -> V,
+    elected: &[CandidateIndex],
+    excluded: &[CandidateIndex],
+) {
+    println!("Candidate totals:");
+    for candidate in continuing.iter().rev() {
+        println!(
+            "    {} votes for candidate {} ({})",
+            votes(*candidate),
+            candidates.get_name(*candidate),
+            candidates.get_party(*candidate)
+        );
+    }
+    println!("Candidate elected: {}", candidates.vec_names(elected));
+    println!("Candidates excluded: {}", candidates.vec_names(excluded));
 }
 
 #[derive(Debug)]
@@ -59,8 +370,8 @@ pub struct CountEngine {
 struct CandidateBundleTransactions(Vec<BundleTransaction>);
 
 impl CandidateBundleTransactions {
-    fn total_votes(&self) -> u32 {
-        self.0.iter().map(|bt| bt.votes).sum()
+    fn total_votes(&self) -> BigRational {
+        total(self.0.iter().map(|bt| &bt.votes))
     }
     fn total_papers(&self) -> u32 {
         self.0.iter().map(|bt| bt.papers).sum()
@@ -71,26 +382,16 @@ impl CandidateBundleTransactions {
 }
 
 impl CountEngine {
-    /// determine the election quota
-    fn determine_quota(total_papers: u32, vacancies: u32) -> u32 {
-        (total_papers / (vacancies + 1)) + 1
-    }
-
-    /// apply transfer value to a number of votes. Rounds down.
-    fn apply_transfer_value(transfer_value: &Ratio<BigInt>, votes: u32) -> u32 {
-        let v: Ratio<BigInt> = Ratio::from_integer(FromPrimitive::from_u32(votes).unwrap());
-        let vr = (transfer_value * v).to_integer();
-        ToPrimitive::to_u32(&vr).unwrap()
-    }
-
     /// bundle ballots together based upon the currently active preference. incrementally updates
     /// `self.candidate_bundle_transactions` with these papers, which must have been removed from
-    /// this structure if they are being distributed as the result of an exclusion or election
+    /// this structure if they are being distributed as the result of an exclusion or election.
+    /// returns the number of votes received by candidates
     fn bundle_ballot_states(
         &mut self,
         ballot_states: Vec<BallotState>,
         transfer_value: Ratio<BigInt>,
-    ) {
+    ) -> BigRational {
+        let mut votes_received = BigRational::zero();
         let mut by_candidate: HashMap<CandidateIndex, Vec<BallotState>> = HashMap::new();
         for ballot_state in ballot_states.into_iter() {
             let candidate_id = match ballot_state.current_preference() {
@@ -110,43 +411,74 @@ impl CountEngine {
                 ballot_states,
                 transfer_value: transfer_value.clone(),
                 papers,
-                votes: CountEngine::apply_transfer_value(&transfer_value, papers),
+                votes: self.rules.apply_transfer_value(&transfer_value, papers),
+                count: self.count_states.len() + 1,
             };
+            votes_received = votes_received + &bt.votes;
             t.0.push(bt);
         }
+        votes_received
     }
 
-    /// distribute bundle transactions as the result of an election or an exclusion.
-    /// moves the state of each bundle transaction on to the next preference, then
-    /// calls on to `bundle_ballot_states`
+    /// distribute bundle transactions as the result of an election or an exclusion, each
+    /// at the transfer value it is paired with. moves the state of each bundle transaction
+    /// on to the next preference, then calls on to `bundle_ballot_states` for the papers at
+    /// each transfer value. `votes_distributed` is the number of votes leaving the previous
+    /// holder of the bundle transactions
     fn distribute_bundle_transactions(
         &mut self,
-        bundle_transactions: Vec<BundleTransaction>,
-        transfer_value: Ratio<BigInt>,
+        bundle_transactions: Vec<(BundleTransaction, BigRational)>,
+        votes_distributed: BigRational,
     ) -> DistributionOutcome {
         // the bundle_transactions should already have been removed from the previous holder
-        let mut ballot_states = Vec::new();
-        let initial_papers: u32 = bundle_transactions.iter().map(|bs| bs.papers).sum();
+        let mut by_transfer_value: Vec<(BigRational, Vec<BallotState>, u32)> = Vec::new();
 
-        for mut bundle_transaction in bundle_transactions {
+        let results = &self.results;
+        for (mut bundle_transaction, transfer_value) in bundle_transactions {
+            let idx = match by_transfer_value
+                .iter()
+                .position(|(tv, _, _)| *tv == transfer_value)
+            {
+                Some(idx) => idx,
+                None => {
+                    by_transfer_value.push((transfer_value, Vec::new(), 0));
+                    by_transfer_value.len() - 1
+                }
+            };
+            let (_, ref mut ballot_states, ref mut papers_exhausted) = by_transfer_value[idx];
             bundle_transaction
                 .ballot_states
                 .par_iter_mut()
                 .for_each(|ballot_state| {
-                    ballot_state.goto_next_preference(&self.results);
+                    ballot_state.goto_next_preference(results);
                 });
             for ballot_state in bundle_transaction.ballot_states {
                 if ballot_state.alive() {
                     ballot_states.push(ballot_state);
+                } else {
+                    *papers_exhausted += ballot_state.count;
                 }
             }
         }
-        let papers_exhausted = initial_papers - ballot_states.len() as u32;
-        let votes_exhausted = CountEngine::apply_transfer_value(&transfer_value, papers_exhausted);
-        self.bundle_ballot_states(ballot_states, transfer_value);
+        let mut papers_exhausted = 0;
+        let mut votes_exhausted = BigRational::zero();
+        let mut votes_received = BigRational::zero();
+        for (transfer_value, ballot_states, papers) in by_transfer_value {
+            papers_exhausted += papers;
+            votes_exhausted =
+                votes_exhausted + self.rules.apply_transfer_value(&transfer_value, papers);
+            votes_received =
+                votes_received + self.bundle_ballot_states(ballot_states, transfer_value);
+        }
+        let votes_lost_by_fraction = votes_distributed - &votes_received - &votes_exhausted;
+        self.distributed.votes_exhausted = &self.distributed.votes_exhausted + &votes_exhausted;
+        self.distributed.papers_exhausted += papers_exhausted;
+        self.distributed.votes_lost_by_fraction =
+            &self.distributed.votes_lost_by_fraction + &votes_lost_by_fraction;
         DistributionOutcome {
             votes_exhausted,
             papers_exhausted,
+            votes_lost_by_fraction,
         }
     }
 
@@ -157,100 +489,172 @@ impl CountEngine {
     /// * `vacancies` - the number of candidates to elect. For the Australian senate, this is `12` (full) or `6` (half)
     /// * `candidates` - the candidates running
     /// * `ballot_states` - the ballots cast
-    /// * `automation` - a queue of automation outcomes
+    /// * `ties` - resolutions for ties which can't be broken by reference to previous counts
+    ///
+    /// The count is conducted under the `Senate2016` rules.
     pub fn new(
         vacancies: u32,
         candidates: CandidateData,
         ballot_states: Vec<BallotState>,
-        automation: VecDeque<usize>,
+        ties: TieResolutions,
+    ) -> CountEngine {
+        CountEngine::with_excluded(vacancies, candidates, ballot_states, ties, &[])
+    }
+
+    /// Create a new STV count engine, with candidates who are to be treated as excluded
+    /// from the start of the count; as in a special count under section 282 where a
+    /// candidate has been found ineligible. Arguments are as for `new`, with:
+    ///
+    /// * `excluded` - the candidates treated as excluded. Ballots skip over these
+    ///   candidates to their next preference; ballots with no other preference are exhausted
+    pub fn with_excluded(
+        vacancies: u32,
+        candidates: CandidateData,
+        ballot_states: Vec<BallotState>,
+        ties: TieResolutions,
+        excluded: &[CandidateIndex],
+    ) -> CountEngine {
+        CountEngine::with_rules(
+            Box::new(Senate2016::new()),
+            vacancies,
+            candidates,
+            ballot_states,
+            ties,
+            excluded,
+        )
+    }
+
+    /// Create a new STV count engine, conducting the count under `rules`. Other arguments
+    /// are as for `with_excluded`.
+    pub fn with_rules(
+        rules: Box<dyn CountingRules>,
+        vacancies: u32,
+        candidates: CandidateData,
+        ballot_states: Vec<BallotState>,
+        ties: TieResolutions,
+        excluded: &[CandidateIndex],
     ) -> CountEngine {
         let total_papers = ballot_states.iter().map(|bs| bs.count).sum();
         let mut engine = CountEngine {
             candidates,
             vacancies,
-            automation,
+            tie_breaking: TieBreaking::new(ties),
+            distributed: DistributionOutcome::new(),
+            votes_retained: BigRational::zero(),
+            quota_papers: None,
             total_papers,
             candidate_bundle_transactions: HashMap::new(),
             count_states: Vec::new(),
-            quota: CountEngine::determine_quota(total_papers, vacancies),
+            quota: rules.quota(total_papers, vacancies),
+            rules,
             results: CountResults::new(),
             actions_pending: VecDeque::new(),
         };
-        engine.bundle_ballot_states(
-            ballot_states,
-            Ratio::from_integer(FromPrimitive::from_u32(1).unwrap()),
-        );
+        for candidate in excluded {
+            engine.results.candidate_excluded(*candidate);
+        }
+        let mut ballot_states = ballot_states;
+        if !excluded.is_empty() {
+            let mut papers_exhausted = 0;
+            let mut remaining = Vec::with_capacity(ballot_states.len());
+            for mut ballot_state in ballot_states {
+                if let Some(candidate) = ballot_state.current_preference() {
+                    if engine.results.candidate_is_inactive(candidate) {
+                        ballot_state.goto_next_preference(&engine.results);
+                    }
+                }
+                if ballot_state.alive() {
+                    remaining.push(ballot_state);
+                } else {
+                    papers_exhausted += ballot_state.count;
+                }
+            }
+            engine.distributed.papers_exhausted = papers_exhausted;
+            engine.distributed.votes_exhausted = whole(papers_exhausted);
+            ballot_states = remaining;
+        }
+        engine.bundle_ballot_states(ballot_states, whole(1));
         engine.push_action(CountAction::FirstCount);
         engine
     }
 
-    #[allow(dead_code)]
-    pub fn print_debug(&self) {
-        println!(
-            "-- CountEngine::print_debug (count {}) --",
-            self.count_states.len()
-        );
-        println!("Candidates: {}", self.candidates.count);
-        println!("Total papers: {}", self.total_papers);
-        println!("Quota: {}", self.quota);
-        println!("Candidate totals:");
-        let mut cbt: Vec<(&CandidateIndex, (u32, u32))> = self
-            .candidate_bundle_transactions
-            .iter()
-            .map(|a| (a.0, (a.1.total_votes(), a.1.total_papers())))
-            .collect();
-        cbt.sort_by(|a, b| b.1.cmp(&a.1));
-        for (candidate_id, (votes, papers)) in cbt {
-            println!(
-                "    {} votes for candidate {} ({}) [{} papers]",
-                votes,
-                self.candidates.get_name(*candidate_id),
-                self.candidates.get_party(*candidate_id),
-                papers
-            );
-        }
-        println!(
-            "Candidates elected: {}",
-            self.candidates.vec_names(self.results.get_elected())
-        );
-        println!(
-            "Candidates excluded: {}",
-            self.candidates.vec_names(self.results.get_excluded())
-        );
+    /// retain the papers which give each elected candidate their quota, so that a casual
+    /// vacancy may be filled by a countback once the count is complete. must be called
+    /// before the count begins
+    pub fn retain_quota_papers(&mut self) {
+        self.quota_papers = Some(HashMap::new());
+    }
+
+    fn record_tie_break(
+        &mut self,
+        kind: TieKind,
+        candidates: &[CandidateIndex],
+        pick: CandidateIndex,
+        method: TieBreakMethod,
+    ) {
+        let count = self.count_states.len();
+        self.tie_breaking
+            .record(count, kind, candidates, pick, method);
+    }
+
+    /// resolve a tie using the supplied tie resolutions, or failing that the tie decision
+    fn resolve_tie(
+        &mut self,
+        kind: TieKind,
+        candidates: &[CandidateIndex],
+    ) -> Result<CandidateIndex, CountError> {
+        let count = self.count_states.len();
+        self.tie_breaking
+            .resolve(count, kind, candidates, &self.candidates)
     }
 
     /// determine the candidates elected, if any. they are returned
     /// in order of election.
-    fn determine_elected_candidates(&mut self) -> Vec<CandidateIndex> {
+    fn determine_elected_candidates(&mut self) -> Result<Vec<CandidateIndex>, CountError> {
         // determine all candidates whose vote total is over the threshold; bin by
         // the number of votes they are holding, so we can determine any ties
-        let mut votes_candidate: HashMap<u32, Vec<CandidateIndex>> = HashMap::new();
+        let mut votes_candidate: HashMap<BigRational, Vec<CandidateIndex>> = HashMap::new();
         for (candidate_id, cbt) in self.candidate_bundle_transactions.iter() {
             if self.results.candidate_is_inactive(*candidate_id) {
                 continue;
             }
             let votes = cbt.total_votes();
-            if votes > self.quota {
-                let v = votes_candidate
-                    .entry(cbt.total_votes())
-                    .or_insert_with(Vec::new);
+            if self.rules.has_quota(&votes, &self.quota) {
+                let v = votes_candidate.entry(votes).or_insert_with(Vec::new);
                 v.push(*candidate_id);
             }
         }
 
         let mut elected: Vec<CandidateIndex> = Vec::new();
-        let mut possible: Vec<(&u32, &Vec<CandidateIndex>)> = votes_candidate.iter().collect();
+        let mut possible: Vec<(&BigRational, &Vec<CandidateIndex>)> =
+            votes_candidate.iter().collect();
         possible.sort_by(|a, b| b.0.cmp(a.0));
         for (_votes, candidate_ids) in possible.into_iter() {
             // no tie in the ordering: elect this candidate
             if candidate_ids.len() == 1 {
                 elected.push(candidate_ids[0]);
+            } else if let Some(tie_broken_candidates) = self.find_tie_breaker(candidate_ids) {
+                // section 273(22): the candidate with the most votes at the most recent count
+                // at which the candidates' totals differed is elected first
+                let pick = tie_broken_candidates[tie_broken_candidates.len() - 1];
+                self.record_tie_break(
+                    TieKind::ElectionOrder,
+                    candidate_ids,
+                    pick,
+                    TieBreakMethod::PreviousCount,
+                );
+                elected.extend(tie_broken_candidates.iter().rev());
             } else {
-                // FIXME
-                panic!("Election ordering ties are not yet implemented.");
+                let mut tied = candidate_ids.clone();
+                while tied.len() > 1 {
+                    let first = self.resolve_tie(TieKind::ElectionOrder, &tied)?;
+                    tied.retain(|c| *c != first);
+                    elected.push(first);
+                }
+                elected.push(tied[0]);
             }
         }
-        elected
+        Ok(elected)
     }
 
     fn push_action(&mut self, action: CountAction) {
@@ -262,20 +666,28 @@ impl CountEngine {
             panic!("Election of a candidate who was already excluded or elected.");
         }
         self.results.candidate_elected(candidate);
-        let candidate_votes = state.votes_per_candidate[&candidate];
-        let candidate_papers = state.papers_per_candidate[&candidate];
-        let excess_votes = if candidate_votes > self.quota {
-            candidate_votes - self.quota
-        } else {
-            0
-        };
-        let transfer_value = Ratio::from_integer(FromPrimitive::from_u32(excess_votes).unwrap())
-            / Ratio::from_integer(FromPrimitive::from_u32(candidate_papers).unwrap());
-        self.push_action(CountAction::ElectionDistribution(candidate, transfer_value));
+        let surplus = self
+            .rules
+            .surplus(&state.votes_per_candidate[&candidate], &self.quota);
+        if self.rules.largest_surplus_first() {
+            // the surplus goes ahead of any smaller surplus yet to be distributed
+            let position = self.actions_pending.iter().position(|action| match action {
+                CountAction::ElectionDistribution(_, pending) => *pending < surplus,
+                _ => false,
+            });
+            if let Some(position) = position {
+                self.actions_pending.insert(
+                    position,
+                    CountAction::ElectionDistribution(candidate, surplus),
+                );
+                return;
+            }
+        }
+        self.push_action(CountAction::ElectionDistribution(candidate, surplus));
     }
 
-    fn build_count_state(&self, votes_exhausted: u32, papers_exhausted: u32) -> CountState {
-        let mut vpc: HashMap<CandidateIndex, u32> = HashMap::new();
+    fn build_count_state(&self, distributed_in_count: DistributionOutcome) -> CountState {
+        let mut vpc: HashMap<CandidateIndex, BigRational> = HashMap::new();
         let mut ppc: HashMap<CandidateIndex, u32> = HashMap::new();
         for (candidate_id, cbts) in self.candidate_bundle_transactions.iter() {
             vpc.insert(*candidate_id, cbts.total_votes());
@@ -284,110 +696,154 @@ impl CountEngine {
         CountState {
             votes_per_candidate: vpc,
             papers_per_candidate: ppc,
-            papers_exhausted,
-            votes_exhausted,
+            votes_exhausted: self.distributed.votes_exhausted.clone(),
+            papers_exhausted: self.distributed.papers_exhausted,
+            votes_exhausted_in_count: distributed_in_count.votes_exhausted,
+            papers_exhausted_in_count: distributed_in_count.papers_exhausted,
+            votes_lost_by_fraction: self.distributed.votes_lost_by_fraction.clone(),
+            votes_lost_by_fraction_in_count: distributed_in_count.votes_lost_by_fraction,
         }
     }
 
     fn process_election_distribution(
         &mut self,
         candidate: CandidateIndex,
-        transfer_value: Ratio<BigInt>,
-    ) {
-        let bundles_to_distribute = self
+        surplus: BigRational,
+    ) -> DistributionOutcome {
+        let bundles_held = self
             .candidate_bundle_transactions
             .remove(&candidate)
-            .unwrap()
-            .0;
-        self.distribute_bundle_transactions(bundles_to_distribute, transfer_value);
+            .unwrap();
+        let votes_held = bundles_held.total_votes();
+        let held = self.quota_papers.as_ref().map(|_| bundles_held.0.clone());
+        let distribution = self
+            .rules
+            .distribute_surplus(&surplus, bundles_held.0, &self.results);
+        if let Some(held) = held {
+            let kept = papers_kept(held, &distribution.bundle_transactions);
+            self.quota_papers.as_mut().unwrap().insert(candidate, kept);
+        }
+        self.votes_retained = &self.votes_retained + votes_held - &distribution.votes;
+        self.distribute_bundle_transactions(distribution.bundle_transactions, distribution.votes)
     }
 
     fn process_exclusion_distribution(
         &mut self,
-        candidate: CandidateIndex,
-        transfer_value: Ratio<BigInt>,
-    ) {
-        let current_bundles = self
-            .candidate_bundle_transactions
-            .remove(&candidate)
-            .unwrap()
-            .0;
+        candidates: Vec<CandidateIndex>,
+        transfer_value: Option<Ratio<BigInt>>,
+    ) -> DistributionOutcome {
         let mut bundles_to_distribute = Vec::new();
-        let mut bundles_to_hold = Vec::new();
-        for bundle in current_bundles {
-            if bundle.transfer_value == transfer_value {
-                bundles_to_distribute.push(bundle);
-            } else {
-                bundles_to_hold.push(bundle);
+        for candidate in candidates {
+            let current_bundles = self
+                .candidate_bundle_transactions
+                .remove(&candidate)
+                .unwrap()
+                .0;
+            let mut bundles_to_hold = Vec::new();
+            for bundle in current_bundles {
+                if transfer_value.is_none() || transfer_value == Some(bundle.transfer_value.clone())
+                {
+                    bundles_to_distribute.push(bundle);
+                } else {
+                    bundles_to_hold.push(bundle);
+                }
+            }
+            // put the remaining bundles, if any, back in
+            if !bundles_to_hold.is_empty() {
+                self.candidate_bundle_transactions
+                    .insert(candidate, CandidateBundleTransactions(bundles_to_hold));
             }
         }
-        // put the remaining bundles, if any, back in
-        if !bundles_to_hold.is_empty() {
-            self.candidate_bundle_transactions
-                .insert(candidate, CandidateBundleTransactions(bundles_to_hold));
+        let votes_distributed = total(bundles_to_distribute.iter().map(|bt| &bt.votes));
+        let bundles_to_distribute = bundles_to_distribute
+            .into_iter()
+            .map(|bt| {
+                let transfer_value = bt.transfer_value.clone();
+                (bt, transfer_value)
+            })
+            .collect();
+        self.distribute_bundle_transactions(bundles_to_distribute, votes_distributed)
+    }
+
+    /// every vote in the count must be held by a candidate, kept by an elected candidate,
+    /// exhausted, or lost by fraction
+    fn check_votes_conserved(&self, count_state: &CountState) -> Result<(), CountError> {
+        let held = total(count_state.votes_per_candidate.values());
+        let accounted = held
+            + &self.votes_retained
+            + &count_state.votes_exhausted
+            + &count_state.votes_lost_by_fraction;
+        if accounted != whole(self.total_papers) {
+            return Err(CountError::VotesNotConserved {
+                count: self.count_states.len(),
+                accounted,
+                total_papers: self.total_papers,
+            });
         }
-        self.distribute_bundle_transactions(bundles_to_distribute, transfer_value);
+        Ok(())
     }
 
     fn find_tie_breaker(&self, candidates: &[CandidateIndex]) -> Option<Vec<CandidateIndex>> {
-        // look back through previous counts, looking for a count where the votes of each of the candidates
-        // are distinct. if found, returns the candidates in ascending vote order
-        for count_state in self.count_states.iter().rev().skip(1) {
-            let mut candidate_votes = Vec::new();
-            let mut vote_set = HashSet::new();
-            for candidate in candidates {
-                let votes = count_state.votes_per_candidate[candidate];
-                candidate_votes.push((*candidate, votes));
-                vote_set.insert(votes);
-            }
-            if vote_set.len() == candidates.len() {
-                candidate_votes.sort_by_key(|&(_, v)| v);
-                return Some(candidate_votes.drain(..).map(|(c, _)| c).collect());
-            }
-        }
-        None
+        find_tie_breaker(
+            &self.count_states,
+            candidates,
+            self.rules.tie_break_earliest_count(),
+        )
     }
 
-    fn exclude_a_candidate(&mut self, count_state: &CountState) {
-        let mut votes_eligible_candidate = Vec::new();
-        for (candidate, votes) in count_state.votes_per_candidate.iter() {
-            if self.results.candidate_is_inactive(*candidate) {
-                continue;
-            }
-            votes_eligible_candidate.push((*candidate, *votes));
-        }
-        assert!(!votes_eligible_candidate.is_empty());
-        let min_votes = votes_eligible_candidate
+    /// determine the candidates to be excluded in bulk under section 273(13A): the candidates
+    /// with the fewest votes, whose combined (notional) vote is fewer than the votes of the
+    /// next highest continuing candidate, and fewer than the leading shortfall (the votes the
+    /// highest continuing candidate needs to reach the quota). this is the Act's Candidate B;
+    /// the wider exclusion of Candidate A, whose notional vote is fewer than the vacancy
+    /// shortfall, is not implemented. as exclusions only take place once all surpluses have
+    /// been distributed, there is no untransferred surplus to take into account.
+    /// returns an empty vector if fewer than two candidates may be excluded.
+    fn bulk_exclusion_candidates(&self, count_state: &CountState) -> Vec<CandidateIndex> {
+        let mut continuing: Vec<(CandidateIndex, &BigRational)> = count_state
+            .votes_per_candidate
             .iter()
-            .map(|&(_, v)| v)
-            .min()
-            .unwrap();
-        let exclusion_candidates: Vec<CandidateIndex> = votes_eligible_candidate
-            .drain(..)
-            .filter(|&(_, v)| v == min_votes)
-            .map(|(c, _)| c)
+            .filter(|(c, _)| !self.results.candidate_is_inactive(**c))
+            .map(|(c, v)| (*c, v))
             .collect();
-
-        let possibilities = exclusion_candidates.len();
-        let to_exclude = if possibilities == 0 {
-            panic!("No candidates left for exclusion, yet we're trying to exclude");
-        } else if possibilities == 1 {
-            exclusion_candidates[0]
+        continuing.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+        let remaining_vacancies = (self.vacancies - self.results.number_elected()) as usize;
+        if continuing.len() <= remaining_vacancies {
+            return Vec::new();
+        }
+        let leading = continuing[continuing.len() - 1].1;
+        let leading_shortfall = if *leading < self.quota {
+            &self.quota - leading
         } else {
-            match self.find_tie_breaker(&exclusion_candidates) {
-                Some(tie_broken_candidates) => tie_broken_candidates[0],
-                None => {
-                    let auto = self.automation.pop_front().unwrap();
-                    exclusion_candidates[auto]
-                }
-            }
+            BigRational::zero()
         };
 
-        self.results.candidate_excluded(to_exclude);
+        let mut to_exclude = 0;
+        let mut notional_vote = BigRational::zero();
+        for idx in 0..(continuing.len() - remaining_vacancies) {
+            notional_vote = notional_vote + continuing[idx].1;
+            if notional_vote >= leading_shortfall {
+                break;
+            }
+            if notional_vote < *continuing[idx + 1].1 {
+                to_exclude = idx + 1;
+            }
+        }
+        if to_exclude < 2 {
+            return Vec::new();
+        }
+        continuing[..to_exclude].iter().map(|&(c, _)| c).collect()
+    }
 
+    /// push the actions to distribute the papers of `candidates`, who have just been excluded
+    fn push_exclusion_distributions(&mut self, candidates: Vec<CandidateIndex>) {
+        if self.rules.exclusion_in_one_count() {
+            self.push_action(CountAction::ExclusionDistribution(candidates, None));
+            return;
+        }
         let mut transfer_values = HashSet::new();
-        {
-            let bundle_transactions = &self.candidate_bundle_transactions[&to_exclude].0;
+        for candidate in candidates.iter() {
+            let bundle_transactions = &self.candidate_bundle_transactions[candidate].0;
             for bundle_transaction in bundle_transactions.iter() {
                 transfer_values.insert(bundle_transaction.transfer_value.clone());
             }
@@ -397,103 +853,805 @@ impl CountEngine {
         transfer_values.reverse();
         for transfer_value in transfer_values {
             self.push_action(CountAction::ExclusionDistribution(
-                to_exclude,
-                transfer_value,
+                candidates.clone(),
+                Some(transfer_value),
             ));
         }
     }
 
-    /// count votes, once (a single count)
-    pub fn count(&mut self) -> CountOutcome {
-        // FIXME: calculate these!
-        let votes_exhausted = 0;
-        let papers_exhausted = 0;
+    fn exclude_a_candidate(&mut self, count_state: &CountState) -> Result<(), CountError> {
+        if self.rules.bulk_exclusion() {
+            let candidates = self.bulk_exclusion_candidates(count_state);
+            if !candidates.is_empty() {
+                for candidate in candidates.iter() {
+                    self.results.candidate_excluded(*candidate);
+                }
+                self.push_exclusion_distributions(candidates);
+                return Ok(());
+            }
+        }
+
+        let continuing = self.continuing_candidates(count_state);
+        assert!(!continuing.is_empty());
+        let to_exclude = lowest_candidate(
+            &continuing,
+            &self.count_states,
+            self.rules.tie_break_earliest_count(),
+            &mut self.tie_breaking,
+            &self.candidates,
+        )?;
 
-        // FIXME: we should do the check for number of continuing candidates = number of
-        // pending vacancies here (no need to keep doing any actions if this is true.)
+        self.results.candidate_excluded(to_exclude);
+        self.push_exclusion_distributions(vec![to_exclude]);
+        Ok(())
+    }
+
+    /// the continuing candidates (neither elected nor excluded), in ascending order of votes
+    fn continuing_candidates(&self, count_state: &CountState) -> Vec<CandidateIndex> {
+        let mut continuing_candidates: Vec<CandidateIndex> = count_state
+            .votes_per_candidate
+            .keys()
+            .filter(|c| !self.results.candidate_is_inactive(**c))
+            .cloned()
+            .collect();
+        continuing_candidates.sort_by(|a, b| {
+            count_state.votes_per_candidate[a].cmp(&count_state.votes_per_candidate[b])
+        });
+        continuing_candidates
+    }
+
+    /// section 273(18); if we're down to N candidates in the running, with N vacancies, the
+    /// remaining candidates are elected. this is checked before any further pending action is
+    /// taken, so that the count finishes without any unnecessary distributions.
+    /// returns true if the remaining candidates were elected
+    fn elect_remaining_candidates(&mut self, count_state: &CountState) -> bool {
+        if !self.rules.elect_remaining() {
+            return false;
+        }
+        let continuing_candidates = self.continuing_candidates(count_state);
+        let remaining_vacancies = self.vacancies - self.results.number_elected();
+        if continuing_candidates.len() as u32 != remaining_vacancies {
+            return false;
+        }
+        for candidate in continuing_candidates.iter().rev() {
+            self.elect(*candidate, count_state);
+        }
+        true
+    }
+}
 
+/// the papers kept by an elected candidate who `held` the given bundle transactions, once
+/// their surplus has been `distributed`: each bundle is kept at the value at which it was
+/// received, less the transfer value at which it was distributed. only meaningful for a
+/// surplus distributed from the last parcel, so a countback requires `LastParcel`
+fn papers_kept(
+    held: Vec<BundleTransaction>,
+    distributed: &[(BundleTransaction, BigRational)],
+) -> Vec<BundleTransaction> {
+    held.into_iter()
+        .filter_map(|bt| {
+            let transfer_value = distributed
+                .iter()
+                .find(|(d, _)| d.count == bt.count && d.transfer_value == bt.transfer_value)
+                .map(|(_, transfer_value)| &bt.transfer_value - transfer_value)
+                .unwrap_or_else(|| bt.transfer_value.clone());
+            if transfer_value <= BigRational::zero() {
+                return None;
+            }
+            Some(BundleTransaction {
+                votes: &transfer_value * whole(bt.papers),
+                transfer_value,
+                ..bt
+            })
+        })
+        .collect()
+}
+
+impl Counter for CountEngine {
+    fn vacancies(&self) -> u32 {
+        self.vacancies
+    }
+
+    fn total_papers(&self) -> u32 {
+        self.total_papers
+    }
+
+    fn quota(&self) -> &BigRational {
+        &self.quota
+    }
+
+    fn count(&mut self) -> Result<CountOutcome, CountError> {
         let action = self.actions_pending.pop_front().unwrap();
-        match action {
+        let distributed_in_count = match action {
             CountAction::FirstCount => {
-                // we don't need to do anything on the first count
+                // we don't need to do anything on the first count; any papers exhausted
+                // had preferences only for candidates excluded from the start of the count
+                self.distributed.clone()
             }
-            CountAction::ExclusionDistribution(candidate, transfer_value) => {
-                self.process_exclusion_distribution(candidate, transfer_value);
+            CountAction::ExclusionDistribution(candidates, transfer_value) => {
+                self.process_exclusion_distribution(candidates, transfer_value)
             }
-            CountAction::ElectionDistribution(candidate, transfer_value) => {
-                self.process_election_distribution(candidate, transfer_value);
+            CountAction::ElectionDistribution(candidate, surplus) => {
+                self.process_election_distribution(candidate, surplus)
             }
-        }
+        };
 
         // determine count totals
-        let count_state = self.build_count_state(votes_exhausted, papers_exhausted);
+        let count_state = self.build_count_state(distributed_in_count);
         self.count_states.push(count_state.clone());
+        self.check_votes_conserved(&count_state)?;
 
         // has anyone been elected in this count?
-        let newly_elected = self.determine_elected_candidates();
+        let newly_elected = self.determine_elected_candidates()?;
         for candidate in newly_elected {
             self.elect(candidate, &count_state);
             if self.results.number_elected() == self.vacancies {
-                return CountOutcome::CountComplete(self.count_states.len(), count_state);
+                return Ok(CountOutcome::CountComplete(
+                    self.count_states.len(),
+                    count_state,
+                ));
             }
         }
 
         // are we done? check the various termination procedures from the Act
-        if self.actions_pending.is_empty() {
-            let mut continuing_candidates: Vec<CandidateIndex> = count_state
-                .votes_per_candidate
-                .keys()
-                .filter(|c| !self.results.candidate_is_inactive(**c))
-                .cloned()
-                .collect();
-            continuing_candidates.sort_by_key(|c| count_state.votes_per_candidate[c]);
-            let remaining_vacancies = self.vacancies - self.results.number_elected();
-            // section 273(18); if we're down to N candidates in the running, with N vacancies, the remaining candidates are elected
-            if continuing_candidates.len() as u32 == remaining_vacancies {
-                for candidate in continuing_candidates.iter().rev() {
-                    self.elect(*candidate, &count_state);
-                }
-                return CountOutcome::CountComplete(self.count_states.len(), count_state);
-            }
+        if self.elect_remaining_candidates(&count_state) {
+            return Ok(CountOutcome::CountComplete(
+                self.count_states.len(),
+                count_state,
+            ));
+        }
+        if self.actions_pending.is_empty() && self.rules.elect_last_two() {
+            let continuing_candidates = self.continuing_candidates(&count_state);
             // section 273(17); if we're down to two candidates in the running, the candidate with the highest number of votes wins - even
             // if they don't have a quota
             if continuing_candidates.len() == 2 {
                 let a = continuing_candidates[0];
                 let b = continuing_candidates[1];
-                if count_state.votes_per_candidate[&a] == count_state.votes_per_candidate[&b] {
-                    // FIXME
-                    panic!("Must manually choose for tie on last spot.");
-                } else {
-                    self.elect(b, &count_state);
-                    return CountOutcome::CountComplete(self.count_states.len(), count_state);
-                }
+                let winner =
+                    if count_state.votes_per_candidate[&a] == count_state.votes_per_candidate[&b] {
+                        self.resolve_tie(TieKind::Election, &[a, b])?
+                    } else {
+                        b
+                    };
+                self.elect(winner, &count_state);
+                return Ok(CountOutcome::CountComplete(
+                    self.count_states.len(),
+                    count_state,
+                ));
             }
         }
 
         // if we don't have anything pending (exclusion or election), then it's
         // time to exclude a candidate
         if self.actions_pending.is_empty() {
-            self.exclude_a_candidate(&count_state);
+            self.exclude_a_candidate(&count_state)?;
+            // the exclusion may leave as many continuing candidates as vacancies, in which
+            // case there's no need to distribute the excluded candidates' papers
+            if self.elect_remaining_candidates(&count_state) {
+                return Ok(CountOutcome::CountComplete(
+                    self.count_states.len(),
+                    count_state,
+                ));
+            }
         }
 
-        CountOutcome::CountContinues(self.count_states.len(), count_state)
+        Ok(CountOutcome::CountContinues(
+            self.count_states.len(),
+            count_state,
+        ))
+    }
+
+    /// for a candidate elected without their surplus being distributed, all of the papers
+    /// they hold
+    fn quota_papers(&self, candidate: CandidateIndex) -> Option<Vec<BundleTransaction>> {
+        let quota_papers = self.quota_papers.as_ref()?;
+        if let Some(kept) = quota_papers.get(&candidate) {
+            return Some(kept.clone());
+        }
+        if !self.results.get_elected().contains(&candidate) {
+            return None;
+        }
+        self.candidate_bundle_transactions
+            .get(&candidate)
+            .map(|cbt| cbt.0.clone())
+    }
+
+    fn print_debug(&self) {
+        println!(
+            "-- CountEngine::print_debug (count {}) --",
+            self.count_states.len()
+        );
+        println!("Rules: {}", self.rules.name());
+        println!("Candidates: {}", self.candidates.count);
+        println!("Total papers: {}", self.total_papers);
+        println!("Quota: {}", self.quota);
+        println!("Candidate totals:");
+        let mut cbt: Vec<(&CandidateIndex, (BigRational, u32))> = self
+            .candidate_bundle_transactions
+            .iter()
+            .map(|a| (a.0, (a.1.total_votes(), a.1.total_papers())))
+            .collect();
+        cbt.sort_by(|a, b| b.1.cmp(&a.1));
+        for (candidate_id, (votes, papers)) in cbt {
+            println!(
+                "    {} votes for candidate {} ({}) [{} papers]",
+                votes,
+                self.candidates.get_name(*candidate_id),
+                self.candidates.get_party(*candidate_id),
+                papers
+            );
+        }
+        println!(
+            "Candidates elected: {}",
+            self.candidates.vec_names(self.results.get_elected())
+        );
+        println!(
+            "Candidates excluded: {}",
+            self.candidates.vec_names(self.results.get_excluded())
+        );
+    }
+
+    fn set_tie_decision(&mut self, tie_decision: TieDecision) {
+        self.tie_breaking.set_tie_decision(tie_decision);
+    }
+
+    fn rules_name(&self) -> &'static str {
+        self.rules.name()
+    }
+
+    fn get_tie_breaks(&self) -> &Vec<TieBreak> {
+        self.tie_breaking.get_tie_breaks()
+    }
+
+    fn get_elected(&self) -> &Vec<CandidateIndex> {
+        self.results.get_elected()
+    }
+
+    fn get_excluded(&self) -> &Vec<CandidateIndex> {
+        self.results.get_excluded()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use fixtures::{ballot_state, candidate_data};
+
+    fn run_count(engine: &mut CountEngine) -> Result<Vec<CandidateIndex>, CountError> {
+        loop {
+            if let CountOutcome::CountComplete(_, _) = engine.count()? {
+                return Ok(engine.results.get_elected().clone());
+            }
+        }
+    }
+
+    fn exclusion_tie_ballots() -> Vec<BallotState> {
+        vec![
+            ballot_state(&[0], 5),
+            ballot_state(&[1, 0], 3),
+            ballot_state(&[2, 0], 3),
+        ]
+    }
+
+    #[test]
+    fn test_exclusion_tie_resolved() {
+        let mut ties = TieResolutions::new();
+        ties.exclusion.push(TieResolution {
+            tie: vec![CandidateIndex(1), CandidateIndex(2)],
+            pick: CandidateIndex(2),
+        });
+        let mut engine = CountEngine::new(
+            1,
+            candidate_data(&["A", "B", "C"]),
+            exclusion_tie_ballots(),
+            ties,
+        );
+        assert_eq!(run_count(&mut engine).unwrap(), vec![CandidateIndex(0)]);
+        assert_eq!(engine.results.get_excluded(), &vec![CandidateIndex(2)]);
+    }
 
     #[test]
-    fn test_apply_transfer_value() {
-        let a: Ratio<BigInt> = Ratio::from_integer(FromPrimitive::from_u32(1).unwrap())
-            / Ratio::from_integer(FromPrimitive::from_u32(3).unwrap());
-        assert!(CountEngine::apply_transfer_value(&a, 0) == 0);
-        assert!(CountEngine::apply_transfer_value(&a, 1) == 0);
-        assert!(CountEngine::apply_transfer_value(&a, 2) == 0);
-        assert!(CountEngine::apply_transfer_value(&a, 3) == 1);
-        assert!(CountEngine::apply_transfer_value(&a, 4) == 1);
-        assert!(CountEngine::apply_transfer_value(&a, 5) == 1);
-        assert!(CountEngine::apply_transfer_value(&a, 6) == 2);
+    fn test_exclusion_tie_unresolved() {
+        let mut engine = CountEngine::new(
+            1,
+            candidate_data(&["A", "B", "C"]),
+            exclusion_tie_ballots(),
+            TieResolutions::new(),
+        );
+        match run_count(&mut engine) {
+            Err(CountError::UnresolvedTie(TieKind::Exclusion, _)) => {}
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tie_resolution_ignores_order() {
+        let mut ties = TieResolutions::new();
+        ties.election.push(TieResolution {
+            tie: vec![CandidateIndex(3), CandidateIndex(1)],
+            pick: CandidateIndex(1),
+        });
+        assert_eq!(
+            ties.resolve(TieKind::Election, &[CandidateIndex(1), CandidateIndex(3)]),
+            Some(CandidateIndex(1))
+        );
+        assert_eq!(
+            ties.resolve(TieKind::Exclusion, &[CandidateIndex(1), CandidateIndex(3)]),
+            None
+        );
+        assert_eq!(
+            ties.resolve(
+                TieKind::Election,
+                &[CandidateIndex(1), CandidateIndex(2), CandidateIndex(3)]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_election_order_tie_broken_by_previous_count() {
+        // A and B are tied on 8 votes, and then elected together on 9 votes;
+        // B was ahead at the first count, so is elected first
+        let ballots = vec![
+            ballot_state(&[0], 7),
+            ballot_state(&[1], 8),
+            ballot_state(&[2, 0], 1),
+            ballot_state(&[3, 0], 1),
+            ballot_state(&[3, 1], 1),
+            ballot_state(&[4], 4),
+        ];
+        let mut engine = CountEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D", "E"]),
+            ballots,
+            TieResolutions::new(),
+        );
+        assert_eq!(
+            run_count(&mut engine).unwrap(),
+            vec![CandidateIndex(1), CandidateIndex(0)]
+        );
+    }
+
+    fn election_order_tie_ballots() -> Vec<BallotState> {
+        vec![
+            ballot_state(&[0], 8),
+            ballot_state(&[1], 8),
+            ballot_state(&[2, 0], 1),
+            ballot_state(&[2, 1], 1),
+            ballot_state(&[3], 3),
+        ]
+    }
+
+    #[test]
+    fn test_election_order_tie_resolved() {
+        let mut ties = TieResolutions::new();
+        ties.election_order.push(TieResolution {
+            tie: vec![CandidateIndex(0), CandidateIndex(1)],
+            pick: CandidateIndex(1),
+        });
+        let mut engine = CountEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            election_order_tie_ballots(),
+            ties,
+        );
+        assert_eq!(
+            run_count(&mut engine).unwrap(),
+            vec![CandidateIndex(1), CandidateIndex(0)]
+        );
+    }
+
+    #[test]
+    fn test_election_order_tie_unresolved() {
+        let mut engine = CountEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            election_order_tie_ballots(),
+            TieResolutions::new(),
+        );
+        match run_count(&mut engine) {
+            Err(CountError::UnresolvedTie(TieKind::ElectionOrder, _)) => {}
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    fn last_two_tie_ballots() -> Vec<BallotState> {
+        vec![
+            ballot_state(&[0], 3),
+            ballot_state(&[1], 3),
+            ballot_state(&[2], 1),
+        ]
+    }
+
+    #[test]
+    fn test_last_two_tie_resolved() {
+        let mut ties = TieResolutions::new();
+        ties.election.push(TieResolution {
+            tie: vec![CandidateIndex(0), CandidateIndex(1)],
+            pick: CandidateIndex(0),
+        });
+        let mut engine = CountEngine::new(
+            1,
+            candidate_data(&["A", "B", "C"]),
+            last_two_tie_ballots(),
+            ties,
+        );
+        assert_eq!(run_count(&mut engine).unwrap(), vec![CandidateIndex(0)]);
+        let tie_breaks = engine.get_tie_breaks();
+        assert_eq!(tie_breaks.len(), 1);
+        assert_eq!(tie_breaks[0].count, 2);
+        assert_eq!(tie_breaks[0].kind, TieKind::Election);
+        assert_eq!(tie_breaks[0].method, TieBreakMethod::Resolution);
+    }
+
+    #[test]
+    fn test_last_two_tie_decided() {
+        let mut engine = CountEngine::new(
+            1,
+            candidate_data(&["A", "B", "C"]),
+            last_two_tie_ballots(),
+            TieResolutions::new(),
+        );
+        engine.set_tie_decision(Box::new(|kind, candidates| {
+            assert_eq!(kind, TieKind::Election);
+            assert_eq!(candidates.len(), 2);
+            Some(CandidateIndex(1))
+        }));
+        assert_eq!(run_count(&mut engine).unwrap(), vec![CandidateIndex(1)]);
+        let tie_breaks = engine.get_tie_breaks();
+        assert_eq!(tie_breaks.len(), 1);
+        assert_eq!(tie_breaks[0].pick, CandidateIndex(1));
+        assert_eq!(tie_breaks[0].method, TieBreakMethod::Decision);
+    }
+
+    #[test]
+    fn test_exhausted_papers() {
+        // A's surplus transfers to B; D is then excluded, and D's papers exhaust
+        let ballots = vec![
+            ballot_state(&[0, 1], 7),
+            ballot_state(&[1], 3),
+            ballot_state(&[2], 4),
+            ballot_state(&[3], 1),
+            ballot_state(&[3, 2], 1),
+            ballot_state(&[3], 1),
+        ];
+        let mut engine = CountEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            ballots,
+            TieResolutions::new(),
+        );
+        run_count(&mut engine).unwrap();
+        let exhausted: Vec<(u32, BigRational, u32, BigRational)> = engine
+            .count_states
+            .iter()
+            .map(|cs| {
+                (
+                    cs.papers_exhausted_in_count,
+                    cs.votes_exhausted_in_count.clone(),
+                    cs.papers_exhausted,
+                    cs.votes_exhausted.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            exhausted,
+            vec![
+                (0, whole(0), 0, whole(0)),
+                (0, whole(0), 0, whole(0)),
+                (2, whole(2), 2, whole(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_votes_lost_by_fraction() {
+        // A's surplus of 2 votes is spread over 7 papers, 3 to B and 4 to C, at a
+        // transfer value of 2/7: B receives no votes, C receives 1, and 1 vote is lost
+        let ballots = vec![
+            ballot_state(&[0, 1], 3),
+            ballot_state(&[0, 2], 4),
+            ballot_state(&[1], 3),
+            ballot_state(&[2], 2),
+            ballot_state(&[3, 1], 2),
+        ];
+        let mut engine = CountEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            ballots,
+            TieResolutions::new(),
+        );
+        run_count(&mut engine).unwrap();
+        let lost: Vec<(BigRational, BigRational)> = engine
+            .count_states
+            .iter()
+            .map(|cs| {
+                (
+                    cs.votes_lost_by_fraction_in_count.clone(),
+                    cs.votes_lost_by_fraction.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(lost[0], (whole(0), whole(0)));
+        assert_eq!(lost[1], (whole(1), whole(1)));
+    }
+
+    #[test]
+    fn test_regrouped_papers_gain_by_fraction() {
+        // two single papers at a transfer value of 1/2, received at different counts, are
+        // worth no votes each. passed on to the same candidate, they are bundled together
+        // and are worth a vote, so the votes lost by fraction are negative
+        let mut engine = CountEngine::new(
+            1,
+            candidate_data(&["A", "B", "C"]),
+            vec![ballot_state(&[0], 3)],
+            TieResolutions::new(),
+        );
+        engine.results.candidate_excluded(CandidateIndex(2));
+        let half = BigRational::new(BigInt::from(1), BigInt::from(2));
+        let bundle = |count| {
+            (
+                BundleTransaction {
+                    ballot_states: vec![ballot_state(&[2, 1], 1)],
+                    transfer_value: half.clone(),
+                    votes: whole(0),
+                    papers: 1,
+                    count,
+                },
+                half.clone(),
+            )
+        };
+        let outcome = engine.distribute_bundle_transactions(vec![bundle(2), bundle(3)], whole(0));
+        assert_eq!(outcome.votes_lost_by_fraction, -whole(1));
+        assert_eq!(engine.distributed.votes_lost_by_fraction, -whole(1));
+        assert_eq!(
+            engine.candidate_bundle_transactions[&CandidateIndex(1)].total_votes(),
+            whole(1)
+        );
+    }
+
+    fn bulk_exclusion_ballots() -> Vec<BallotState> {
+        vec![
+            ballot_state(&[0], 20),
+            ballot_state(&[1], 15),
+            ballot_state(&[2], 10),
+            ballot_state(&[3, 2], 2),
+            ballot_state(&[4, 2], 3),
+        ]
+    }
+
+    #[test]
+    fn test_bulk_exclusion() {
+        // D and E have 5 votes between them, fewer than C's 10; they are excluded together
+        let mut engine = CountEngine::with_rules(
+            Box::new(Senate2016 {
+                bulk_exclusion: true,
+                ..Senate2016::default()
+            }),
+            1,
+            candidate_data(&["A", "B", "C", "D", "E"]),
+            bulk_exclusion_ballots(),
+            TieResolutions::new(),
+            &[],
+        );
+        engine.count().unwrap();
+        assert_eq!(
+            engine.get_excluded(),
+            &vec![CandidateIndex(3), CandidateIndex(4)]
+        );
+        run_count(&mut engine).unwrap();
+        // the bulk exclusion is distributed in a single count
+        assert_eq!(
+            engine.count_states[1].votes_per_candidate[&CandidateIndex(2)],
+            whole(15)
+        );
+    }
+
+    #[test]
+    fn test_bulk_exclusion_leading_shortfall() {
+        // F and G have 3 votes between them, fewer than E's 6; but not fewer than A's leading
+        // shortfall of 1 vote, so F is excluded alone
+        let ballots = vec![
+            ballot_state(&[0], 10),
+            ballot_state(&[1], 9),
+            ballot_state(&[2], 8),
+            ballot_state(&[3], 7),
+            ballot_state(&[4], 6),
+            ballot_state(&[5, 4], 1),
+            ballot_state(&[6, 4], 2),
+        ];
+        let mut engine = CountEngine::with_rules(
+            Box::new(Senate2016 {
+                bulk_exclusion: true,
+                ..Senate2016::default()
+            }),
+            3,
+            candidate_data(&["A", "B", "C", "D", "E", "F", "G"]),
+            ballots,
+            TieResolutions::new(),
+            &[],
+        );
+        assert_eq!(engine.quota, whole(11));
+        engine.count().unwrap();
+        assert_eq!(engine.get_excluded(), &vec![CandidateIndex(5)]);
+    }
+
+    #[test]
+    fn test_no_bulk_exclusion() {
+        let mut engine = CountEngine::new(
+            1,
+            candidate_data(&["A", "B", "C", "D", "E"]),
+            bulk_exclusion_ballots(),
+            TieResolutions::new(),
+        );
+        run_count(&mut engine).unwrap();
+        assert_eq!(
+            engine.count_states[1].votes_per_candidate[&CandidateIndex(2)],
+            whole(12)
+        );
+    }
+
+    #[test]
+    fn test_continuing_candidates_equal_vacancies() {
+        // once C is excluded, A and B fill the two vacancies: C's papers are not distributed
+        let ballots = vec![
+            ballot_state(&[0], 4),
+            ballot_state(&[1], 3),
+            ballot_state(&[2, 1], 2),
+        ];
+        let mut engine = CountEngine::new(
+            2,
+            candidate_data(&["A", "B", "C"]),
+            ballots,
+            TieResolutions::new(),
+        );
+        match engine.count().unwrap() {
+            CountOutcome::CountComplete(1, _) => {}
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+        assert_eq!(
+            engine.get_elected(),
+            &vec![CandidateIndex(0), CandidateIndex(1)]
+        );
+    }
+
+    #[test]
+    fn test_special_recount_excluded() {
+        // A is ineligible: A's papers go straight to their next preference, or exhaust
+        let ballots = vec![
+            ballot_state(&[0, 1], 5),
+            ballot_state(&[0], 2),
+            ballot_state(&[1], 2),
+            ballot_state(&[2], 4),
+        ];
+        let mut engine = CountEngine::with_excluded(
+            1,
+            candidate_data(&["A", "B", "C"]),
+            ballots,
+            TieResolutions::new(),
+            &[CandidateIndex(0)],
+        );
+        assert_eq!(engine.total_papers, 13);
+        assert_eq!(run_count(&mut engine).unwrap(), vec![CandidateIndex(1)]);
+        let first = &engine.count_states[0];
+        assert_eq!(first.votes_per_candidate[&CandidateIndex(1)], whole(7));
+        assert_eq!(first.papers_exhausted, 2);
+        assert!(!first.votes_per_candidate.contains_key(&CandidateIndex(0)));
+    }
+
+    #[test]
+    fn test_scotland_count() {
+        // a count worked by hand. quota 11; A's surplus of 4 is transferred at 0.26666,
+        // losing 0.0001 by truncation. C's papers, at two different values, are then
+        // transferred in a single stage, and exhaust; D is excluded, and B elected
+        let mut engine = CountEngine::with_rules(
+            Box::new(Scotland2007),
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            vec![
+                ballot_state(&[0, 1], 12),
+                ballot_state(&[0, 2], 3),
+                ballot_state(&[1], 4),
+                ballot_state(&[2], 5),
+                ballot_state(&[3, 2], 6),
+            ],
+            TieResolutions::new(),
+            &[],
+        );
+        assert_eq!(engine.quota, whole(11));
+        assert_eq!(
+            run_count(&mut engine).unwrap(),
+            vec![CandidateIndex(0), CandidateIndex(1)]
+        );
+        // the votes of each candidate, then the votes exhausted and lost by fraction, at
+        // each stage; to five decimal places
+        let votes: [&[(u16, u32)]; 3] = [
+            &[(0, 1_500_000), (1, 400_000), (2, 500_000), (3, 600_000)],
+            &[(1, 719_992), (2, 579_998), (3, 600_000)],
+            &[(1, 719_992), (3, 600_000)],
+        ];
+        let exhausted = [0, 0, 579_998];
+        let lost = [0, 10, 10];
+        let places = |v: u32| whole(v) / whole(100_000);
+        assert_eq!(engine.count_states.len(), votes.len());
+        for (stage, state) in engine.count_states.iter().enumerate() {
+            let expected: HashMap<CandidateIndex, BigRational> = votes[stage]
+                .iter()
+                .map(|(c, v)| (CandidateIndex(*c), places(*v)))
+                .collect();
+            assert_eq!(state.votes_per_candidate, expected);
+            assert_eq!(state.votes_exhausted, places(exhausted[stage]));
+            assert_eq!(state.votes_lost_by_fraction, places(lost[stage]));
+        }
+        assert_eq!(engine.count_states[2].papers_exhausted_in_count, 8);
+    }
+
+    fn count_state(votes: &[u32]) -> CountState {
+        CountState {
+            votes_per_candidate: votes
+                .iter()
+                .enumerate()
+                .map(|(c, v)| (CandidateIndex(c as u16), whole(*v)))
+                .collect(),
+            papers_per_candidate: HashMap::new(),
+            votes_exhausted: whole(0),
+            papers_exhausted: 0,
+            votes_exhausted_in_count: whole(0),
+            papers_exhausted_in_count: 0,
+            votes_lost_by_fraction: whole(0),
+            votes_lost_by_fraction_in_count: whole(0),
+        }
+    }
+
+    #[test]
+    fn test_find_tie_breaker_earliest() {
+        let count_states = vec![
+            count_state(&[3, 4]),
+            count_state(&[5, 4]),
+            count_state(&[6, 6]),
+        ];
+        let tied = [CandidateIndex(0), CandidateIndex(1)];
+        assert_eq!(
+            find_tie_breaker(&count_states, &tied, false),
+            Some(vec![CandidateIndex(1), CandidateIndex(0)])
+        );
+        assert_eq!(
+            find_tie_breaker(&count_states, &tied, true),
+            Some(vec![CandidateIndex(0), CandidateIndex(1)])
+        );
+    }
+
+    #[test]
+    fn test_quota_papers() {
+        // A is elected on 10 votes at the second count, with a quota of 8. under the last
+        // parcel method, the 4 papers received from C go on at a transfer value of one half;
+        // A keeps their first preferences, and the other half of the last parcel
+        let ballots = vec![
+            ballot_state(&[0, 1], 6),
+            ballot_state(&[1], 5),
+            ballot_state(&[2, 0, 3], 4),
+            ballot_state(&[3], 6),
+        ];
+        let mut engine = CountEngine::with_rules(
+            Box::new(Senate2016 {
+                surplus: SurplusMethod::LastParcel,
+                ..Senate2016::default()
+            }),
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            ballots,
+            TieResolutions::new(),
+            &[],
+        );
+        engine.retain_quota_papers();
+        run_count(&mut engine).unwrap();
+        let kept: Vec<(BigRational, u32)> = engine
+            .quota_papers(CandidateIndex(0))
+            .unwrap()
+            .iter()
+            .map(|bt| (bt.transfer_value.clone(), bt.papers))
+            .collect();
+        assert_eq!(kept, vec![(whole(1), 6), (whole(1) / whole(2), 4)]);
+        assert!(engine.quota_papers(CandidateIndex(2)).is_none());
     }
 }
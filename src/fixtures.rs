@@ -0,0 +1,42 @@
+/*
+ * fixtures shared by the tests of the counts and their rules
+ */
+
+use defs::*;
+use num::rational::BigRational;
+use rules::whole;
+
+/// candidates with the given names, none of them grouped
+pub fn candidate_data(names: &[&str]) -> CandidateData {
+    CandidateData {
+        count: names.len(),
+        names: names.iter().map(|n| n.to_string()).collect(),
+        parties: names.iter().map(|_| String::new()).collect(),
+        tickets: Vec::new(),
+    }
+}
+
+/// `count` papers with the preferences in `form`, at their first preference
+pub fn ballot_state(form: &[u16], count: u32) -> BallotState {
+    BallotState {
+        form: form.iter().map(|c| CandidateIndex(*c)).collect(),
+        count,
+        active_preference: 0,
+    }
+}
+
+/// the papers in `ballot_states`, received at `transfer_value` at count `count`
+pub fn bundle_transaction(
+    ballot_states: Vec<BallotState>,
+    transfer_value: BigRational,
+    count: usize,
+) -> BundleTransaction {
+    let papers = ballot_states.iter().map(|bs| bs.count).sum();
+    BundleTransaction {
+        ballot_states,
+        votes: &transfer_value * whole(papers),
+        transfer_value,
+        papers,
+        count,
+    }
+}
@@ -0,0 +1,340 @@
+/*
+ * the House of Representatives: a preferential count for a single vacancy
+ */
+
+use aec::data::candidates::AECAllCandidateRow;
+use defs::*;
+use engine::{
+    ascending_by_votes, lowest_candidate, print_candidate_totals, CountError, CountOutcome,
+    CountState, Counter, TieBreaking,
+};
+use num::rational::BigRational;
+use rules::whole;
+use std::collections::HashMap;
+
+/// a preferential (instant-runoff) count for a single vacancy, under section 274 of the
+/// Commonwealth Electoral Act 1918. a candidate with an absolute majority of votes is elected;
+/// there is no surplus to distribute. the candidate with the fewest votes is excluded at each
+/// count, and their papers passed on at full value, until only two candidates remain. the
+/// count continues to that point even once a candidate has been elected, so that the final
+/// count gives the two-candidate-preferred result
+pub struct IrvEngine {
+    total_papers: u32,
+    /// an absolute majority of the papers
+    quota: BigRational,
+    candidates: CandidateData,
+    /// the papers held by each continuing candidate
+    papers: HashMap<CandidateIndex, Vec<BallotState>>,
+    elected: Vec<CandidateIndex>,
+    /// the excluded candidates; the elected candidate remains in the count
+    results: CountResults,
+    /// the candidate excluded at the last count, whose papers are yet to be distributed
+    pending_exclusion: Option<CandidateIndex>,
+    papers_exhausted: u32,
+    count_states: Vec<CountState>,
+    tie_breaking: TieBreaking,
+}
+
+impl IrvEngine {
+    pub fn new(
+        candidates: CandidateData,
+        ballot_states: Vec<BallotState>,
+        ties: TieResolutions,
+    ) -> IrvEngine {
+        let total_papers = ballot_states.iter().map(|bs| bs.count).sum();
+        let mut papers: HashMap<CandidateIndex, Vec<BallotState>> = (0..candidates.count)
+            .map(|idx| (CandidateIndex(idx as u16), Vec::new()))
+            .collect();
+        for ballot_state in ballot_states {
+            let candidate = ballot_state.current_preference().unwrap();
+            papers.get_mut(&candidate).unwrap().push(ballot_state);
+        }
+        IrvEngine {
+            total_papers,
+            quota: whole(total_papers / 2 + 1),
+            candidates,
+            papers,
+            elected: Vec::new(),
+            results: CountResults::new(),
+            pending_exclusion: None,
+            papers_exhausted: 0,
+            count_states: Vec::new(),
+            tie_breaking: TieBreaking::new(ties),
+        }
+    }
+
+    /// distribute the papers of an excluded candidate to the next continuing candidate
+    /// on each paper. returns the number of papers exhausted
+    fn distribute(&mut self, excluded: CandidateIndex) -> u32 {
+        let mut papers_exhausted = 0;
+        for mut ballot_state in self.papers.remove(&excluded).unwrap() {
+            ballot_state.goto_next_preference(&self.results);
+            match ballot_state.current_preference() {
+                Some(candidate) => self.papers.get_mut(&candidate).unwrap().push(ballot_state),
+                None => papers_exhausted += ballot_state.count,
+            }
+        }
+        self.papers_exhausted += papers_exhausted;
+        papers_exhausted
+    }
+
+    fn votes(&self, candidate: CandidateIndex) -> u32 {
+        self.papers[&candidate].iter().map(|bs| bs.count).sum()
+    }
+
+    fn build_count_state(&self, papers_exhausted_in_count: u32) -> CountState {
+        let mut vpc = HashMap::new();
+        let mut ppc = HashMap::new();
+        for candidate in self.papers.keys() {
+            let votes = self.votes(*candidate);
+            vpc.insert(*candidate, whole(votes));
+            ppc.insert(*candidate, votes);
+        }
+        CountState {
+            votes_per_candidate: vpc,
+            papers_per_candidate: ppc,
+            votes_exhausted: whole(self.papers_exhausted),
+            papers_exhausted: self.papers_exhausted,
+            votes_exhausted_in_count: whole(papers_exhausted_in_count),
+            papers_exhausted_in_count,
+            votes_lost_by_fraction: whole(0),
+            votes_lost_by_fraction_in_count: whole(0),
+        }
+    }
+
+    /// the candidates in the count, in ascending order of votes
+    fn continuing_candidates(&self) -> Vec<CandidateIndex> {
+        ascending_by_votes(self.papers.keys().cloned(), |c| self.votes(c))
+    }
+
+    fn exclude_a_candidate(&mut self, continuing: &[CandidateIndex]) -> Result<(), CountError> {
+        let to_exclude = lowest_candidate(
+            continuing,
+            &self.count_states,
+            false,
+            &mut self.tie_breaking,
+            &self.candidates,
+        )?;
+        self.results.candidate_excluded(to_exclude);
+        self.pending_exclusion = Some(to_exclude);
+        Ok(())
+    }
+}
+
+/// the two-candidate-preferred result, from the final count: the votes of the two candidates
+/// remaining, in descending order of votes
+pub fn two_candidate_preferred(final_state: &CountState) -> Vec<(CandidateIndex, BigRational)> {
+    let mut result: Vec<(CandidateIndex, BigRational)> = final_state
+        .votes_per_candidate
+        .iter()
+        .map(|(c, v)| (*c, v.clone()))
+        .collect();
+    result.sort_by(|a, b| (&b.1, a.0).cmp(&(&a.1, b.0)));
+    result
+}
+
+/// the candidate data for a House count, from the candidates standing in the division, in
+/// ballot paper order. House candidates aren't grouped, so there are no tickets
+pub fn load_candidate_data(candidates: Vec<AECAllCandidateRow>) -> Result<CandidateData, String> {
+    check_candidate_count(candidates.len())?;
+    Ok(CandidateData {
+        count: candidates.len(),
+        names: candidates
+            .iter()
+            .map(|candidate| format!("{}, {}", candidate.surname, candidate.ballot_given_nm))
+            .collect(),
+        parties: candidates
+            .iter()
+            .map(|candidate| candidate.party_ballot_nm.clone())
+            .collect(),
+        tickets: Vec::new(),
+    })
+}
+
+impl Counter for IrvEngine {
+    fn vacancies(&self) -> u32 {
+        1
+    }
+
+    fn total_papers(&self) -> u32 {
+        self.total_papers
+    }
+
+    fn quota(&self) -> &BigRational {
+        &self.quota
+    }
+
+    fn rules_name(&self) -> &'static str {
+        "house"
+    }
+
+    fn count(&mut self) -> Result<CountOutcome, CountError> {
+        let papers_exhausted_in_count = match self.pending_exclusion.take() {
+            Some(candidate) => self.distribute(candidate),
+            None => 0,
+        };
+        let count_state = self.build_count_state(papers_exhausted_in_count);
+        self.count_states.push(count_state.clone());
+        let number = self.count_states.len();
+
+        let continuing = self.continuing_candidates();
+        if self.elected.is_empty() {
+            if let Some(leader) = continuing.last() {
+                if count_state.votes_per_candidate[leader] >= self.quota {
+                    self.elected.push(*leader);
+                }
+            }
+        }
+        if continuing.len() <= 2 {
+            if self.elected.is_empty() {
+                // papers may exhaust under optional preferential voting, leaving neither
+                // candidate with an absolute majority
+                let winner = match continuing.len() {
+                    2 if self.votes(continuing[0]) == self.votes(continuing[1]) => self
+                        .tie_breaking
+                        .resolve(number, TieKind::Election, &continuing, &self.candidates)?,
+                    _ => continuing[continuing.len() - 1],
+                };
+                self.elected.push(winner);
+            }
+            return Ok(CountOutcome::CountComplete(number, count_state));
+        }
+        self.exclude_a_candidate(&continuing)?;
+        Ok(CountOutcome::CountContinues(number, count_state))
+    }
+
+    fn get_elected(&self) -> &Vec<CandidateIndex> {
+        &self.elected
+    }
+
+    fn get_excluded(&self) -> &Vec<CandidateIndex> {
+        self.results.get_excluded()
+    }
+
+    fn get_tie_breaks(&self) -> &Vec<TieBreak> {
+        self.tie_breaking.get_tie_breaks()
+    }
+
+    fn set_tie_decision(&mut self, tie_decision: TieDecision) {
+        self.tie_breaking.set_tie_decision(tie_decision);
+    }
+
+    fn print_debug(&self) {
+        println!(
+            "-- IrvEngine::print_debug (count {}) --",
+            self.count_states.len()
+        );
+        println!("Candidates: {}", self.candidates.count);
+        println!("Total papers: {}", self.total_papers);
+        println!("Absolute majority: {}", self.quota);
+        print_candidate_totals(
+            &self.candidates,
+            &self.continuing_candidates(),
+            |c| self.votes(c),
+            &self.elected,
+            self.results.get_excluded(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aec::data::candidates::process_house_fd;
+    use fixtures::{ballot_state, candidate_data};
+
+    fn run_count(engine: &mut IrvEngine) -> Vec<CountState> {
+        let mut states = Vec::new();
+        loop {
+            match engine.count().unwrap() {
+                CountOutcome::CountComplete(_, state) => {
+                    states.push(state);
+                    return states;
+                }
+                CountOutcome::CountContinues(_, state) => states.push(state),
+            }
+        }
+    }
+
+    #[test]
+    fn test_full_distribution_and_2cp() {
+        // A has an absolute majority on first preferences, but the count continues to the
+        // final two candidates; C's papers then flow to B
+        let mut engine = IrvEngine::new(
+            candidate_data(&["A", "B", "C", "D"]),
+            vec![
+                ballot_state(&[0, 1, 2, 3], 52),
+                ballot_state(&[1, 0, 2, 3], 30),
+                ballot_state(&[2, 1, 0, 3], 12),
+                ballot_state(&[3, 0, 1, 2], 6),
+            ],
+            TieResolutions::new(),
+        );
+        assert_eq!(engine.quota, whole(51));
+        let states = run_count(&mut engine);
+        assert_eq!(states.len(), 3);
+        assert_eq!(engine.get_elected(), &vec![CandidateIndex(0)]);
+        assert_eq!(
+            engine.get_excluded(),
+            &vec![CandidateIndex(3), CandidateIndex(2)]
+        );
+        assert_eq!(
+            two_candidate_preferred(&states[2]),
+            vec![
+                (CandidateIndex(0), whole(58)),
+                (CandidateIndex(1), whole(42))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elected_on_preferences() {
+        let mut engine = IrvEngine::new(
+            candidate_data(&["A", "B", "C"]),
+            vec![
+                ballot_state(&[0, 2, 1], 40),
+                ballot_state(&[1, 2, 0], 35),
+                ballot_state(&[2, 1, 0], 25),
+            ],
+            TieResolutions::new(),
+        );
+        let states = run_count(&mut engine);
+        assert_eq!(states.len(), 2);
+        assert_eq!(engine.get_elected(), &vec![CandidateIndex(1)]);
+        assert_eq!(
+            two_candidate_preferred(&states[1]),
+            vec![
+                (CandidateIndex(1), whole(60)),
+                (CandidateIndex(0), whole(40))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_from_candidates() {
+        let csv_data = r##"txn_nm,nom_ty,state_ab,div_nm,ticket,ballot_position,surname,ballot_given_nm,party_ballot_nm,occupation,address_1,address_2,postcode,suburb,address_state_ab,contact_work_ph,contact_home_ph,postal_address_1,postal_address_2,postal_suburb,postal_postcode,contact_fax,postal_state_ab,contact_mobile_no,contact_email
+2019 Federal Election,H,TAS,Clark,,2,BAGGINS,Frodo,Hobbits,,,,,,,,,,,,,,,,
+2019 Federal Election,H,TAS,Clark,,1,LION,Aslan,Narnians,,,,,,,,,,,,,,,,
+2019 Federal Election,H,TAS,Clark,,3,BOMBADIL,Tom,,,,,,,,,,,,,,,,,
+"##;
+        let rows = process_house_fd(csv_data.as_bytes(), "clark").unwrap();
+        let cd = load_candidate_data(rows).unwrap();
+        assert_eq!(
+            cd.names,
+            vec!["LION, Aslan", "BAGGINS, Frodo", "BOMBADIL, Tom"]
+        );
+        assert!(cd.tickets.is_empty());
+        let mut engine = IrvEngine::new(
+            cd,
+            vec![
+                ballot_state(&[0, 2, 1], 40),
+                ballot_state(&[1, 2, 0], 35),
+                ballot_state(&[2, 1, 0], 25),
+            ],
+            TieResolutions::new(),
+        );
+        run_count(&mut engine);
+        // Tom's papers elect Frodo
+        assert_eq!(engine.get_elected(), &vec![CandidateIndex(1)]);
+    }
+}
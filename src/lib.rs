@@ -7,7 +7,15 @@ extern crate serde_derive;
 
 pub mod aec;
 pub mod configuration;
+pub mod countback;
 pub mod defs;
 pub mod engine;
+#[cfg(test)]
+mod fixtures;
+pub mod house;
+pub mod meek;
+pub mod nswlc;
 pub mod output;
+pub mod rules;
+pub mod senate2013;
 pub mod senate2015;
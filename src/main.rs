@@ -8,35 +8,200 @@ extern crate serde_json;
 extern crate toml;
 
 use clap::{App, Arg};
-use dividebatur::configuration::{read_config, CountGroup, CountTask};
+use dividebatur::aec::data::formalpreferences::BadLines;
+use dividebatur::configuration::{read_config, CountGroup, CountMethod, CountTask};
+use dividebatur::countback::CountbackEngine;
+use dividebatur::defs::*;
 use dividebatur::engine::*;
+use dividebatur::house::IrvEngine;
+use dividebatur::meek::MeekEngine;
+use dividebatur::nswlc::NswLegislativeCouncil;
 use dividebatur::output::{write_summary, CountOutput};
 use num_format::{Locale, ToFormattedString};
 use rayon::prelude::*;
-use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
 use std::time::Instant;
 
 struct TaskSummary {
     total_papers: u32,
 }
 
-fn run_task(group: &CountGroup, task: &CountTask, debug: bool) -> Result<TaskSummary, String> {
+/// ask the user to decide a tie on the terminal, on behalf of the Australian Electoral Officer
+fn prompt_tie_decision(
+    slug: &str,
+    names: &[String],
+    kind: TieKind,
+    candidates: &[CandidateIndex],
+) -> Option<CandidateIndex> {
+    // hold stdin for the duration, so that prompts from parallel counts don't interleave
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    println!(
+        "{}: unresolved {:?} tie, please pick a candidate:",
+        slug, kind
+    );
+    for (idx, candidate) in candidates.iter().enumerate() {
+        println!("  {}: {}", idx, names[candidate.0 as usize]);
+    }
+    print!("> ");
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    input.read_line(&mut line).ok()?;
+    let choice: usize = line.trim().parse().ok()?;
+    candidates.get(choice).cloned()
+}
+
+/// run the count through to completion, recording each count in `output`. returns the
+/// state of the count at its completion
+fn run_engine(
+    engine: &mut dyn Counter,
+    mut output: Option<&mut CountOutput>,
+    debug: bool,
+) -> Result<CountState, String> {
+    loop {
+        let outcome = match engine.count() {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                return Err(format!("Count failed: {}", error));
+            }
+        };
+        match outcome {
+            CountOutcome::CountComplete(ncounts, state) => {
+                if let Some(ref mut output) = output {
+                    output.add_count(ncounts, &state, &*engine);
+                }
+                if debug {
+                    engine.print_debug();
+                    println!("Election complete after {} counts.", ncounts);
+                }
+                return Ok(state);
+            }
+            CountOutcome::CountContinues(ncounts, state) => {
+                if let Some(ref mut output) = output {
+                    output.add_count(ncounts, &state, &*engine);
+                }
+                if debug {
+                    engine.print_debug();
+                }
+            }
+        }
+    }
+}
+
+/// read the ballots for a count with group voting tickets: the papers marked above the line
+/// follow the tickets, and are counted along with the papers marked below the line
+fn read_gvt_ballots(task: &CountTask, cd: &CandidateData) -> Result<Vec<BallotState>, String> {
+    let tickets = task.tickets.as_ref().unwrap();
+    let first_preferences = task.first_preferences.as_ref().unwrap();
+    let rows = match dividebatur::aec::data::gvt::load(tickets, &task.state) {
+        Ok(rows) => rows,
+        Err(error) => {
+            return Err(format!(
+                "Couldn't read group voting tickets file: {:?}",
+                error
+            ));
+        }
+    };
+    let gvt = dividebatur::senate2013::load_group_voting_tickets(&rows, cd)?;
+    let ticket_votes =
+        match dividebatur::aec::data::firstprefs::load_ticket_votes(first_preferences, &task.state)
+        {
+            Ok(ticket_votes) => ticket_votes,
+            Err(error) => {
+                return Err(format!("Couldn't read first preferences file: {:?}", error));
+            }
+        };
+    let btl = match dividebatur::aec::data::btl::read_file(&task.preferences, &gvt.candidate_ids) {
+        Ok(btl) => btl,
+        Err(error) => {
+            return Err(format!("Couldn't read preferences file: {:?}", error));
+        }
+    };
+    let mut ballot_states =
+        dividebatur::senate2013::ticket_ballot_states(&gvt, &ticket_votes, &task.ticket_lots)?;
+    ballot_states.extend(btl);
+    Ok(ballot_states)
+}
+
+fn run_task(
+    group: &CountGroup,
+    task: &CountTask,
+    debug: bool,
+    interactive: bool,
+) -> Result<TaskSummary, String> {
     if debug {
         println!("-> running task: {}", task.description);
     }
     let mut output: CountOutput = CountOutput::new(&task.slug);
-    let candidates = match dividebatur::aec::data::candidates::load(&task.candidates, &task.state) {
+    let candidates = match task.division {
+        Some(ref division) => {
+            dividebatur::aec::data::candidates::load_house(&task.candidates, division)
+        }
+        None => dividebatur::aec::data::candidates::load(&task.candidates, &task.state),
+    };
+    let candidates = match candidates {
         Ok(rows) => rows,
         Err(error) => {
             return Err(format!("Couldn't read candidates file: {:?}", error));
         }
     };
-    let cd = dividebatur::senate2015::load_candidate_data(candidates);
+    let cd = match task.division {
+        Some(_) => dividebatur::house::load_candidate_data(candidates)?,
+        None => dividebatur::senate2015::load_candidate_data(candidates)?,
+    };
     output.set_candidates(&cd);
+    let ties = task.tie_resolutions(&cd)?;
+    let excluded = task.excluded_candidates(&cd)?;
+    // the countback is conducted on the papers which elected the vacating candidate
+    let countback = task
+        .countback_candidates(&cd)?
+        .map(|(vacating, contesting)| (cd.clone(), vacating, contesting, ties.clone()));
 
-    let prefpath = &task.preferences;
-    let ballot_states =
-        dividebatur::aec::data::formalpreferences::read_file(prefpath, &cd.tickets, cd.count);
+    let ballot_states = if task.method == CountMethod::SenateGvt {
+        read_gvt_ballots(task, &cd)?
+    } else if let Some(ref division) = task.division {
+        let ballot_states = match dividebatur::aec::data::housepreferences::read_file(
+            &task.preferences,
+            division,
+            cd.count,
+        ) {
+            Ok(ballot_states) => ballot_states,
+            Err(error) => {
+                return Err(format!("Couldn't read preferences file: {:?}", error));
+            }
+        };
+        ballot_states
+    } else {
+        let prefs = match dividebatur::aec::data::formalpreferences::read_file(
+            &task.preferences,
+            &cd.tickets,
+            &cd.names,
+            task.bad_lines,
+        ) {
+            Ok(prefs) => prefs,
+            Err(error) => {
+                return Err(format!("Couldn't read preferences file: {}", error));
+            }
+        };
+        if !prefs.skipped.is_empty() {
+            println!(
+                "{}: skipped {} lines which couldn't be parsed",
+                task.slug,
+                prefs.skipped.len()
+            );
+            if debug {
+                for error in &prefs.skipped {
+                    println!("    {}", error);
+                }
+            }
+        }
+        output.set_formality(&prefs.formality);
+        if task.bad_lines == BadLines::Skip {
+            output.set_skipped_lines(&prefs.skipped);
+        }
+        output.set_statistics(&prefs.statistics);
+        prefs.ballot_states
+    };
 
     if debug {
         println!(
@@ -45,31 +210,143 @@ fn run_task(group: &CountGroup, task: &CountTask, debug: bool) -> Result<TaskSum
         );
     }
 
-    let mut automation = VecDeque::new();
-    automation.push_back(0);
-    let mut engine = CountEngine::new(task.vacancies as u32, cd, ballot_states, automation);
-    output.set_parameters(&group, &task, &engine);
-    while {
-        let outcome = engine.count();
-        match outcome {
-            CountOutcome::CountComplete(ncounts, _state) => {
-                if debug {
-                    engine.print_debug();
-                    println!("Election complete after {} counts.", ncounts);
-                }
-                false
+    // the section 282 recount is conducted on the same ballots
+    let recount = if task.method == CountMethod::DoubleDissolution {
+        Some((cd.clone(), ballot_states.clone(), ties.clone()))
+    } else {
+        None
+    };
+
+    // the count is repeated with each of the seeds, on the same ballots
+    let repeats = if task.seeds.is_empty() {
+        None
+    } else {
+        Some((cd.clone(), ballot_states.clone(), ties.clone()))
+    };
+
+    let names = cd.names.clone();
+    let mut engine: Box<dyn Counter> = match task.method {
+        CountMethod::Meek => {
+            let mut engine = MeekEngine::new(task.vacancies as u32, cd, ballot_states, ties);
+            if let Some(tolerance) = task.tolerance {
+                engine.set_tolerance(tolerance);
             }
-            CountOutcome::CountContinues(_, _) => {
-                if debug {
-                    engine.print_debug();
-                }
-                true
+            Box::new(engine)
+        }
+        CountMethod::House => Box::new(IrvEngine::new(cd, ballot_states, ties)),
+        _ => {
+            let mut engine = CountEngine::with_rules(
+                task.counting_rules().unwrap(),
+                task.vacancies as u32,
+                cd,
+                ballot_states,
+                ties,
+                &excluded,
+            );
+            if countback.is_some() {
+                engine.retain_quota_papers();
             }
+            Box::new(engine)
         }
-    } {}
+    };
+    if interactive {
+        let slug = task.slug.clone();
+        let names = names.clone();
+        engine.set_tie_decision(Box::new(move |kind, candidates| {
+            prompt_tie_decision(&slug, &names, kind, candidates)
+        }));
+    }
+    output.set_parameters(group, task, &*engine);
+    let final_state = run_engine(&mut *engine, Some(&mut output), debug)?;
+    if task.method == CountMethod::House {
+        output.set_two_candidate_preferred(&final_state);
+    }
+
+    if let Some((cd, vacating, contesting, ties)) = countback {
+        if debug {
+            println!("-> countback: {}", task.description);
+        }
+        let quota_papers = match engine.quota_papers(vacating) {
+            Some(quota_papers) => quota_papers,
+            None => {
+                return Err(format!(
+                    "{} was not elected, so there is no vacancy to fill",
+                    names[vacating.0 as usize]
+                ));
+            }
+        };
+        let mut countback_engine =
+            CountbackEngine::new(cd, vacating, quota_papers, &contesting, ties);
+        if interactive {
+            let slug = format!("{} (countback)", task.slug);
+            let names = names.clone();
+            countback_engine.set_tie_decision(Box::new(move |kind, candidates| {
+                prompt_tie_decision(&slug, &names, kind, candidates)
+            }));
+        }
+        let mut countback_output = CountOutput::new(&task.slug);
+        run_engine(&mut countback_engine, Some(&mut countback_output), debug)?;
+        output.set_countback(
+            vacating,
+            &contesting,
+            countback_engine.get_elected(),
+            countback_output,
+        );
+    }
+
+    if let Some((cd, ballot_states, ties)) = recount {
+        if debug {
+            println!("-> section 282 recount: {}", task.description);
+        }
+        let mut recount_engine = CountEngine::with_rules(
+            task.counting_rules().unwrap(),
+            (task.vacancies / 2) as u32,
+            cd,
+            ballot_states,
+            ties,
+            &[],
+        );
+        if interactive {
+            let slug = format!("{} (section 282 recount)", task.slug);
+            let names = names.clone();
+            recount_engine.set_tie_decision(Box::new(move |kind, candidates| {
+                prompt_tie_decision(&slug, &names, kind, candidates)
+            }));
+        }
+        run_engine(&mut recount_engine, None, debug)?;
+        output.set_allocation(engine.get_elected(), recount_engine.get_elected());
+    }
+
+    if let Some((cd, ballot_states, ties)) = repeats {
+        let mut outcomes = Vec::new();
+        for seed in &task.seeds {
+            if debug {
+                println!("-> repeated with seed {}: {}", seed, task.description);
+            }
+            let mut repeat_engine = CountEngine::with_rules(
+                Box::new(NswLegislativeCouncil::new(*seed)),
+                task.vacancies as u32,
+                cd.clone(),
+                ballot_states.clone(),
+                ties.clone(),
+                &excluded,
+            );
+            if interactive {
+                let slug = format!("{} (seed {})", task.slug, seed);
+                let names = names.clone();
+                repeat_engine.set_tie_decision(Box::new(move |kind, candidates| {
+                    prompt_tie_decision(&slug, &names, kind, candidates)
+                }));
+            }
+            run_engine(&mut repeat_engine, None, debug)?;
+            outcomes.push((*seed, repeat_engine.get_elected().to_vec()));
+        }
+        output.set_seed_outcomes(&outcomes);
+    }
+
     output.close();
     Ok(TaskSummary {
-        total_papers: engine.total_papers,
+        total_papers: engine.total_papers(),
     })
 }
 
@@ -83,6 +360,11 @@ fn main() {
                 .short("d")
                 .help("Enable debugging output"),
         )
+        .arg(
+            Arg::with_name("interactive")
+                .short("i")
+                .help("Prompt for a decision on ties without a configured resolution"),
+        )
         .arg(
             Arg::with_name("INPUT")
                 .multiple(true)
@@ -92,6 +374,7 @@ fn main() {
         .get_matches();
 
     let debug = matches.occurrences_of("debug") > 0;
+    let interactive = matches.occurrences_of("interactive") > 0;
     let work = read_config(matches.values_of("INPUT").unwrap().collect());
     write_summary(&work);
     let mut elapsed: Vec<(String, String, u32, u128)> = Vec::new();
@@ -99,21 +382,27 @@ fn main() {
         let mut stats: Vec<(String, String, u32, u128)> = group
             .counts
             .par_iter()
-            .map(|task| {
+            .filter_map(|task| {
                 let start = Instant::now();
-                let result = run_task(&group, task, debug).unwrap();
-                (
+                let result = match run_task(&group, task, debug, interactive) {
+                    Ok(result) => result,
+                    Err(error) => {
+                        println!("{}: {}: {}", group.filename, task.slug, error);
+                        return None;
+                    }
+                };
+                Some((
                     group.filename.clone(),
                     task.slug.clone(),
                     result.total_papers,
                     start.elapsed().as_millis(),
-                )
+                ))
             })
             .collect();
         elapsed.append(&mut stats);
     }
 
-    elapsed.sort_by_key(|(_filename, _slug, _papers, time_ms)| time_ms.clone());
+    elapsed.sort_by_key(|(_filename, _slug, _papers, time_ms)| *time_ms);
     elapsed.reverse();
     println!("|----------------------|--------------|--------------|--------------|");
     println!("| config               | count        | papers       | elapsed (ms) |");
@@ -0,0 +1,562 @@
+/*
+ * Meek's method of STV
+ */
+
+use defs::*;
+use engine::{
+    find_tie_breaker, lowest_candidate, CountError, CountOutcome, CountState, Counter, TieBreaking,
+};
+use num::rational::BigRational;
+use num::{BigInt, FromPrimitive};
+use rules::{total, whole};
+use std::collections::HashMap;
+
+/// votes and keep values are held as fixed-point numbers, to nine decimal places
+const SCALE: u64 = 1_000_000_000;
+
+/// the keep values of the elected candidates are recalculated until the total of their
+/// surpluses is no more than this number of votes
+pub const DEFAULT_TOLERANCE: f64 = 0.000_01;
+
+/// a limit on the number of iterations in a single count, in case the keep values
+/// oscillate rather than converging; the count fails if it is reached
+const MAX_ITERATIONS: usize = 1000;
+
+fn to_rational(value: u64) -> BigRational {
+    BigRational::new(
+        BigInt::from_u64(value).unwrap(),
+        BigInt::from_u64(SCALE).unwrap(),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Hopeful,
+    Elected,
+    Excluded,
+}
+
+/// the result of passing every paper through the candidates, in order of preference,
+/// with each candidate keeping their keep value of whatever reaches them
+struct Distribution {
+    votes: Vec<u64>,
+    papers: Vec<u32>,
+    votes_exhausted: u64,
+    papers_exhausted: u32,
+    quota: u64,
+}
+
+/// a count by Meek's method. rather than transferring papers, each candidate has a keep
+/// value: the fraction of the value of each paper reaching them which they keep, with
+/// the remainder passing to the next preference. hopeful candidates keep the whole value
+/// and excluded candidates keep nothing, while the keep values of elected candidates are
+/// reduced, iteratively, until each of them holds just a quota. as votes are exhausted
+/// the quota is recalculated, so that surpluses pass through elected candidates rather
+/// than being lost. each count ends with the election of the candidates holding a quota
+/// or, if there are none, the exclusion of the candidate with the fewest votes
+pub struct MeekEngine {
+    vacancies: u32,
+    total_papers: u32,
+    /// the quota at the start of the count
+    quota: BigRational,
+    /// the quota after the most recent iteration
+    current_quota: u64,
+    tolerance: u64,
+    max_iterations: usize,
+    candidates: CandidateData,
+    ballot_states: Vec<BallotState>,
+    keep_values: Vec<u64>,
+    status: Vec<Status>,
+    results: CountResults,
+    count_states: Vec<CountState>,
+    tie_breaking: TieBreaking,
+}
+
+impl MeekEngine {
+    pub fn new(
+        vacancies: u32,
+        candidates: CandidateData,
+        ballot_states: Vec<BallotState>,
+        ties: TieResolutions,
+    ) -> MeekEngine {
+        let total_papers = ballot_states.iter().map(|bs| bs.count).sum();
+        let mut engine = MeekEngine {
+            vacancies,
+            total_papers,
+            quota: whole(0),
+            current_quota: 0,
+            tolerance: 0,
+            max_iterations: MAX_ITERATIONS,
+            keep_values: vec![SCALE; candidates.count],
+            status: vec![Status::Hopeful; candidates.count],
+            candidates,
+            ballot_states,
+            results: CountResults::new(),
+            count_states: Vec::new(),
+            tie_breaking: TieBreaking::new(ties),
+        };
+        engine.set_tolerance(DEFAULT_TOLERANCE);
+        // every candidate keeps the whole of each paper reaching them, so the first count
+        // needs no iteration
+        let distribution = engine.distribute();
+        engine.quota = to_rational(distribution.quota);
+        engine.current_quota = distribution.quota;
+        engine
+    }
+
+    /// set the total surplus, in votes, at which the keep values are considered to
+    /// have converged
+    pub fn set_tolerance(&mut self, tolerance: f64) {
+        self.tolerance = (tolerance * SCALE as f64) as u64;
+    }
+
+    /// pass every paper through the candidates, at the current keep values. the value
+    /// kept by each candidate is truncated, and the quota is the votes held by the
+    /// candidates divided by one more than the number of vacancies, plus the smallest
+    /// possible fraction of a vote
+    fn distribute(&self) -> Distribution {
+        let mut votes = vec![0; self.candidates.count];
+        let mut papers = vec![0; self.candidates.count];
+        let mut votes_exhausted = 0;
+        let mut papers_exhausted = 0;
+        for ballot_state in self.ballot_states.iter() {
+            let mut remaining = SCALE;
+            let mut reached = false;
+            for candidate in ballot_state.form.iter() {
+                let idx = candidate.0 as usize;
+                if self.status[idx] == Status::Excluded {
+                    continue;
+                }
+                reached = true;
+                let kept = (u128::from(remaining) * u128::from(self.keep_values[idx])
+                    / u128::from(SCALE)) as u64;
+                if kept > 0 {
+                    votes[idx] += kept * u64::from(ballot_state.count);
+                    papers[idx] += ballot_state.count;
+                }
+                remaining -= kept;
+                if remaining == 0 {
+                    break;
+                }
+            }
+            votes_exhausted += remaining * u64::from(ballot_state.count);
+            if !reached {
+                papers_exhausted += ballot_state.count;
+            }
+        }
+        let held: u64 = votes.iter().sum();
+        Distribution {
+            votes,
+            papers,
+            votes_exhausted,
+            papers_exhausted,
+            quota: held / u64::from(self.vacancies + 1) + 1,
+        }
+    }
+
+    /// recalculate the keep values of the elected candidates until the total of their
+    /// surpluses is within the tolerance, or the keep values no longer change. fails if
+    /// neither happens within the limit on iterations
+    fn converge(&mut self) -> Result<Distribution, CountError> {
+        let mut iterations = 0;
+        loop {
+            let distribution = self.distribute();
+            self.current_quota = distribution.quota;
+            iterations += 1;
+            let elected: Vec<usize> = (0..self.candidates.count)
+                .filter(|idx| self.status[*idx] == Status::Elected)
+                .collect();
+            let surplus: u64 = elected
+                .iter()
+                .map(|idx| distribution.votes[*idx].saturating_sub(distribution.quota))
+                .sum();
+            if surplus <= self.tolerance {
+                return Ok(distribution);
+            }
+            if iterations >= self.max_iterations {
+                return Err(CountError::NotConverged {
+                    iterations,
+                    surplus: to_rational(surplus),
+                });
+            }
+            let mut changed = false;
+            for idx in elected {
+                let votes = distribution.votes[idx];
+                if votes == 0 {
+                    continue;
+                }
+                // round up, so that the elected candidates don't fall short of the quota
+                let keep_value = u128::from(self.keep_values[idx]) * u128::from(distribution.quota);
+                let keep_value = ((keep_value + u128::from(votes) - 1) / u128::from(votes))
+                    .min(u128::from(SCALE)) as u64;
+                if keep_value != self.keep_values[idx] {
+                    self.keep_values[idx] = keep_value;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return Ok(distribution);
+            }
+        }
+    }
+
+    fn build_count_state(&self, distribution: &Distribution) -> CountState {
+        let mut vpc: HashMap<CandidateIndex, BigRational> = HashMap::new();
+        let mut ppc: HashMap<CandidateIndex, u32> = HashMap::new();
+        for idx in 0..self.candidates.count {
+            if self.status[idx] == Status::Excluded {
+                continue;
+            }
+            let candidate = CandidateIndex(idx as u16);
+            vpc.insert(candidate, to_rational(distribution.votes[idx]));
+            ppc.insert(candidate, distribution.papers[idx]);
+        }
+        let votes_exhausted = to_rational(distribution.votes_exhausted);
+        let votes_lost_by_fraction =
+            whole(self.total_papers) - total(vpc.values()) - &votes_exhausted;
+        let (votes_exhausted_in_count, papers_exhausted_in_count, votes_lost_by_fraction_in_count) =
+            match self.count_states.last() {
+                Some(previous) => (
+                    &votes_exhausted - &previous.votes_exhausted,
+                    distribution
+                        .papers_exhausted
+                        .saturating_sub(previous.papers_exhausted),
+                    &votes_lost_by_fraction - &previous.votes_lost_by_fraction,
+                ),
+                None => (
+                    votes_exhausted.clone(),
+                    distribution.papers_exhausted,
+                    votes_lost_by_fraction.clone(),
+                ),
+            };
+        CountState {
+            votes_per_candidate: vpc,
+            papers_per_candidate: ppc,
+            votes_exhausted,
+            papers_exhausted: distribution.papers_exhausted,
+            votes_exhausted_in_count,
+            papers_exhausted_in_count,
+            votes_lost_by_fraction,
+            votes_lost_by_fraction_in_count,
+        }
+    }
+
+    /// the hopeful candidates, in ascending order of votes
+    fn hopeful_candidates(&self, distribution: &Distribution) -> Vec<CandidateIndex> {
+        let mut hopeful: Vec<CandidateIndex> = (0..self.candidates.count)
+            .filter(|idx| self.status[*idx] == Status::Hopeful)
+            .map(|idx| CandidateIndex(idx as u16))
+            .collect();
+        hopeful.sort_by_key(|c| distribution.votes[c.0 as usize]);
+        hopeful
+    }
+
+    /// determine the hopeful candidates holding a quota, in order of election
+    fn determine_elected_candidates(
+        &mut self,
+        distribution: &Distribution,
+    ) -> Result<Vec<CandidateIndex>, CountError> {
+        let mut votes_candidate: HashMap<u64, Vec<CandidateIndex>> = HashMap::new();
+        for candidate in self.hopeful_candidates(distribution) {
+            let votes = distribution.votes[candidate.0 as usize];
+            if votes >= distribution.quota {
+                votes_candidate.entry(votes).or_default().push(candidate);
+            }
+        }
+        let mut possible: Vec<(u64, Vec<CandidateIndex>)> = votes_candidate.drain().collect();
+        possible.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut elected = Vec::new();
+        for (_votes, candidate_ids) in possible {
+            if candidate_ids.len() == 1 {
+                elected.push(candidate_ids[0]);
+            } else if let Some(tie_broken_candidates) =
+                find_tie_breaker(&self.count_states, &candidate_ids, false)
+            {
+                let pick = tie_broken_candidates[tie_broken_candidates.len() - 1];
+                self.tie_breaking.record(
+                    self.count_states.len(),
+                    TieKind::ElectionOrder,
+                    &candidate_ids,
+                    pick,
+                    TieBreakMethod::PreviousCount,
+                );
+                elected.extend(tie_broken_candidates.iter().rev());
+            } else {
+                let mut tied = candidate_ids.clone();
+                while tied.len() > 1 {
+                    let first = self.tie_breaking.resolve(
+                        self.count_states.len(),
+                        TieKind::ElectionOrder,
+                        &tied,
+                        &self.candidates,
+                    )?;
+                    tied.retain(|c| *c != first);
+                    elected.push(first);
+                }
+                elected.push(tied[0]);
+            }
+        }
+        Ok(elected)
+    }
+
+    fn elect(&mut self, candidate: CandidateIndex) {
+        self.status[candidate.0 as usize] = Status::Elected;
+        self.results.candidate_elected(candidate);
+    }
+
+    fn exclude_a_candidate(&mut self, distribution: &Distribution) -> Result<(), CountError> {
+        let hopeful = self.hopeful_candidates(distribution);
+        assert!(!hopeful.is_empty());
+        let to_exclude = lowest_candidate(
+            &hopeful,
+            &self.count_states,
+            false,
+            &mut self.tie_breaking,
+            &self.candidates,
+        )?;
+        self.status[to_exclude.0 as usize] = Status::Excluded;
+        self.keep_values[to_exclude.0 as usize] = 0;
+        self.results.candidate_excluded(to_exclude);
+        Ok(())
+    }
+
+    /// if there are only as many hopeful candidates as remaining vacancies, they are
+    /// elected. returns true if the remaining candidates were elected
+    fn elect_remaining_candidates(&mut self, distribution: &Distribution) -> bool {
+        let hopeful = self.hopeful_candidates(distribution);
+        let remaining_vacancies = self.vacancies - self.results.number_elected();
+        if hopeful.len() as u32 != remaining_vacancies {
+            return false;
+        }
+        for candidate in hopeful.iter().rev() {
+            self.elect(*candidate);
+        }
+        true
+    }
+}
+
+impl Counter for MeekEngine {
+    fn vacancies(&self) -> u32 {
+        self.vacancies
+    }
+
+    fn total_papers(&self) -> u32 {
+        self.total_papers
+    }
+
+    fn quota(&self) -> &BigRational {
+        &self.quota
+    }
+
+    fn rules_name(&self) -> &'static str {
+        "meek"
+    }
+
+    fn count(&mut self) -> Result<CountOutcome, CountError> {
+        let distribution = self.converge()?;
+        let count_state = self.build_count_state(&distribution);
+        self.count_states.push(count_state.clone());
+        let number = self.count_states.len();
+
+        let newly_elected = self.determine_elected_candidates(&distribution)?;
+        let any_elected = !newly_elected.is_empty();
+        for candidate in newly_elected {
+            self.elect(candidate);
+            if self.results.number_elected() == self.vacancies {
+                return Ok(CountOutcome::CountComplete(number, count_state));
+            }
+        }
+        if self.elect_remaining_candidates(&distribution) {
+            return Ok(CountOutcome::CountComplete(number, count_state));
+        }
+
+        // the keep values of the candidates just elected are determined in the next count;
+        // if nobody was elected, a candidate is excluded
+        if !any_elected {
+            self.exclude_a_candidate(&distribution)?;
+            if self.elect_remaining_candidates(&distribution) {
+                return Ok(CountOutcome::CountComplete(number, count_state));
+            }
+        }
+
+        Ok(CountOutcome::CountContinues(number, count_state))
+    }
+
+    fn get_elected(&self) -> &Vec<CandidateIndex> {
+        self.results.get_elected()
+    }
+
+    fn get_excluded(&self) -> &Vec<CandidateIndex> {
+        self.results.get_excluded()
+    }
+
+    fn get_tie_breaks(&self) -> &Vec<TieBreak> {
+        self.tie_breaking.get_tie_breaks()
+    }
+
+    fn set_tie_decision(&mut self, tie_decision: TieDecision) {
+        self.tie_breaking.set_tie_decision(tie_decision);
+    }
+
+    fn print_debug(&self) {
+        println!(
+            "-- MeekEngine::print_debug (count {}) --",
+            self.count_states.len()
+        );
+        println!("Candidates: {}", self.candidates.count);
+        println!("Total papers: {}", self.total_papers);
+        println!("Quota: {}", to_rational(self.current_quota));
+        println!("Keep values:");
+        for (idx, keep_value) in self.keep_values.iter().enumerate() {
+            if self.status[idx] == Status::Elected {
+                println!(
+                    "    {} for candidate {}",
+                    to_rational(*keep_value),
+                    self.candidates.get_name(CandidateIndex(idx as u16))
+                );
+            }
+        }
+        println!(
+            "Candidates elected: {}",
+            self.candidates.vec_names(self.results.get_elected())
+        );
+        println!(
+            "Candidates excluded: {}",
+            self.candidates.vec_names(self.results.get_excluded())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{ballot_state, candidate_data};
+    use num::ToPrimitive;
+
+    fn run_count(engine: &mut MeekEngine) -> Vec<CountState> {
+        let mut states = Vec::new();
+        loop {
+            match engine.count().unwrap() {
+                CountOutcome::CountComplete(_, state) => {
+                    states.push(state);
+                    return states;
+                }
+                CountOutcome::CountContinues(_, state) => states.push(state),
+            }
+        }
+    }
+
+    fn to_f64(value: &BigRational) -> f64 {
+        value.numer().to_f64().unwrap() / value.denom().to_f64().unwrap()
+    }
+
+    fn votes(state: &CountState, candidate: u16) -> f64 {
+        to_f64(&state.votes_per_candidate[&CandidateIndex(candidate)])
+    }
+
+    #[test]
+    fn test_quota_reduced_as_votes_exhaust() {
+        let mut engine = MeekEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            vec![
+                ballot_state(&[0, 1], 60),
+                ballot_state(&[2], 25),
+                ballot_state(&[3], 15),
+            ],
+            TieResolutions::new(),
+        );
+        assert_eq!(engine.quota, to_rational(33_333_333_334));
+        let states = run_count(&mut engine);
+        assert_eq!(
+            engine.get_elected(),
+            &vec![CandidateIndex(0), CandidateIndex(1)]
+        );
+        assert_eq!(engine.get_excluded(), &vec![CandidateIndex(3)]);
+        assert_eq!(states.len(), 3);
+        // A keeps a third of their votes, and the remainder passes to B
+        assert!((votes(&states[1], 0) - 100.0 / 3.0).abs() < 0.001);
+        assert!((votes(&states[1], 1) - 80.0 / 3.0).abs() < 0.001);
+        // once D is excluded their papers exhaust, and the quota falls to 85 / 3
+        assert_eq!(states[2].papers_exhausted, 15);
+        assert!((votes(&states[2], 0) - 85.0 / 3.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_votes_pass_through_elected_candidate() {
+        let mut engine = MeekEngine::new(
+            2,
+            candidate_data(&["A", "B", "C", "D"]),
+            vec![
+                ballot_state(&[0], 50),
+                ballot_state(&[1, 0, 3], 10),
+                ballot_state(&[2], 22),
+                ballot_state(&[3], 18),
+            ],
+            TieResolutions::new(),
+        );
+        let states = run_count(&mut engine);
+        assert_eq!(
+            engine.get_elected(),
+            &vec![CandidateIndex(0), CandidateIndex(3)]
+        );
+        // B's papers reach D through A, who keeps 5/13 of each paper
+        let last = &states[states.len() - 1];
+        assert!((votes(last, 3) - (18.0 + 10.0 * 8.0 / 13.0)).abs() < 0.001);
+        assert!((votes(last, 0) - 60.0 * 5.0 / 13.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        // A and B each pass part of their surplus to the other, so the keep values are
+        // reached only by iteration; the total surplus at the second count is within
+        // the tolerance, with the quota a quarter of the 100 votes
+        let surplus = |tolerance: f64| {
+            let mut engine = MeekEngine::new(
+                3,
+                candidate_data(&["A", "B", "C", "D"]),
+                vec![
+                    ballot_state(&[0, 1, 2], 45),
+                    ballot_state(&[1, 0, 3], 35),
+                    ballot_state(&[2], 12),
+                    ballot_state(&[3], 8),
+                ],
+                TieResolutions::new(),
+            );
+            engine.set_tolerance(tolerance);
+            let states = run_count(&mut engine);
+            votes(&states[1], 0) + votes(&states[1], 1) - 50.0
+        };
+        assert!(surplus(1.0) <= 1.0);
+        assert!(surplus(1.0) > surplus(0.01));
+        assert!(surplus(0.000_001) <= 0.000_001);
+    }
+
+    #[test]
+    fn test_not_converged() {
+        // as in test_tolerance, the keep values of A and B take more than one iteration
+        let mut engine = MeekEngine::new(
+            3,
+            candidate_data(&["A", "B", "C", "D"]),
+            vec![
+                ballot_state(&[0, 1, 2], 45),
+                ballot_state(&[1, 0, 3], 35),
+                ballot_state(&[2], 12),
+                ballot_state(&[3], 8),
+            ],
+            TieResolutions::new(),
+        );
+        engine.max_iterations = 1;
+        engine.count().unwrap();
+        match engine.count() {
+            Err(CountError::NotConverged {
+                iterations,
+                surplus,
+            }) => {
+                assert_eq!(iterations, 1);
+                assert!(to_f64(&surplus) > 1.0);
+            }
+            _ => panic!("expected the count to fail to converge"),
+        }
+    }
+}
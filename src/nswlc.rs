@@ -0,0 +1,312 @@
+/*
+ * the New South Wales Legislative Council, where surpluses are transferred by random selection
+ */
+
+use defs::*;
+use num::rational::BigRational;
+use num::ToPrimitive;
+use rules::*;
+use std::collections::HashMap;
+
+/// a deterministic pseudo-random number generator (SplitMix64). the selection of papers in
+/// a count is entirely determined by the seed, so a count can be reproduced exactly
+#[derive(Debug, Clone)]
+pub struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    pub fn new(seed: u64) -> Prng {
+        Prng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// a number in `0..n`, without bias
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0);
+        let zone = u64::MAX - (u64::MAX % n);
+        loop {
+            let v = self.next_u64();
+            if v < zone {
+                return v % n;
+            }
+        }
+    }
+}
+
+/// select `wanted` of the papers in `ballot_states` at random, each paper being equally likely
+/// to be selected. the ballot states are split as required
+fn select_papers(
+    rng: &mut Prng,
+    ballot_states: Vec<BallotState>,
+    mut wanted: u32,
+) -> Vec<BallotState> {
+    let mut remaining: u32 = ballot_states.iter().map(|bs| bs.count).sum();
+    let mut selected = Vec::new();
+    for ballot_state in ballot_states {
+        let mut count = 0;
+        for _ in 0..ballot_state.count {
+            if wanted == 0 {
+                break;
+            }
+            if rng.below(u64::from(remaining)) < u64::from(wanted) {
+                count += 1;
+                wanted -= 1;
+            }
+            remaining -= 1;
+        }
+        if count > 0 {
+            selected.push(BallotState {
+                count,
+                ..ballot_state
+            });
+        }
+    }
+    selected
+}
+
+#[derive(Debug, Clone)]
+/// the rules for the New South Wales Legislative Council, under Schedule 5 of the
+/// Parliamentary Electorates and Elections Act 1912. every paper has a value of one: rather
+/// than transferring all of an elected candidate's papers at a fractional value, as many
+/// papers as the surplus are selected at random from the parcel which elected them, and
+/// transferred at full value. the papers are selected using a pseudo-random number
+/// generator, so that the count is reproduced exactly by the same `seed`
+pub struct NswLegislativeCouncil {
+    pub seed: u64,
+    rng: Prng,
+}
+
+impl NswLegislativeCouncil {
+    pub fn new(seed: u64) -> NswLegislativeCouncil {
+        NswLegislativeCouncil {
+            seed,
+            rng: Prng::new(seed),
+        }
+    }
+}
+
+impl CountingRules for NswLegislativeCouncil {
+    fn name(&self) -> &'static str {
+        "nsw_legislative_council"
+    }
+
+    fn quota(&self, total_papers: u32, vacancies: u32) -> BigRational {
+        whole((total_papers / (vacancies + 1)) + 1)
+    }
+
+    fn has_quota(&self, votes: &BigRational, quota: &BigRational) -> bool {
+        votes >= quota
+    }
+
+    fn apply_transfer_value(&self, transfer_value: &BigRational, papers: u32) -> BigRational {
+        (transfer_value * whole(papers)).floor()
+    }
+
+    /// the papers in the last parcel received by the candidate (for a candidate elected at
+    /// the first count, all of their papers) are arranged by next available preference. each
+    /// continuing candidate receives their proportion of the surplus, with any papers left
+    /// over due to fractions going to the candidates with the largest fractions; the papers
+    /// transferred are selected at random. exhausted papers are set aside, and if there are
+    /// no more unexhausted papers than the surplus, all of them are transferred
+    fn distribute_surplus(
+        &mut self,
+        surplus: &BigRational,
+        bundle_transactions: Vec<BundleTransaction>,
+        results: &CountResults,
+    ) -> SurplusDistribution {
+        let surplus = surplus.to_integer().to_u32().unwrap();
+        let last = bundle_transactions.iter().map(|bt| bt.count).max();
+        let mut by_candidate: HashMap<CandidateIndex, Vec<BallotState>> = HashMap::new();
+        for bundle_transaction in bundle_transactions {
+            if Some(bundle_transaction.count) != last {
+                continue;
+            }
+            for ballot_state in bundle_transaction.ballot_states {
+                let mut next = ballot_state.clone();
+                next.goto_next_preference(results);
+                if let Some(candidate) = next.current_preference() {
+                    by_candidate
+                        .entry(candidate)
+                        .or_insert_with(Vec::new)
+                        .push(ballot_state);
+                }
+            }
+        }
+        // arrange in order of candidate, and each candidate's papers in order of form, so
+        // that the selection is reproducible whatever order the papers were read in
+        let mut by_candidate: Vec<(CandidateIndex, Vec<BallotState>, u32)> = by_candidate
+            .into_iter()
+            .map(|(c, mut ballot_states)| {
+                ballot_states.sort_by_cached_key(|bs| (bs.form.to_vec(), bs.active_preference));
+                let papers = ballot_states.iter().map(|bs| bs.count).sum();
+                (c, ballot_states, papers)
+            })
+            .collect();
+        by_candidate.sort_by_key(|(c, _, _)| *c);
+        let unexhausted: u32 = by_candidate.iter().map(|(_, _, papers)| papers).sum();
+
+        let mut allocation: Vec<u32> = by_candidate.iter().map(|(_, _, papers)| *papers).collect();
+        if unexhausted > surplus {
+            let shares: Vec<(u64, u64)> = by_candidate
+                .iter()
+                .map(|(_, _, papers)| {
+                    let share = u64::from(surplus) * u64::from(*papers);
+                    (
+                        share / u64::from(unexhausted),
+                        share % u64::from(unexhausted),
+                    )
+                })
+                .collect();
+            allocation = shares.iter().map(|(papers, _)| *papers as u32).collect();
+            let mut left_over = surplus - allocation.iter().sum::<u32>();
+            // the papers left over go to the largest fractions, with ties decided by lot
+            let mut by_fraction: Vec<(u64, u64, usize)> = shares
+                .iter()
+                .enumerate()
+                .map(|(idx, (_, fraction))| (*fraction, self.rng.next_u64(), idx))
+                .collect();
+            by_fraction.sort_by(|a, b| b.cmp(a));
+            for (_, _, idx) in by_fraction {
+                if left_over == 0 {
+                    break;
+                }
+                allocation[idx] += 1;
+                left_over -= 1;
+            }
+        }
+
+        let mut ballot_states = Vec::new();
+        for ((_, candidate_ballot_states, _), wanted) in by_candidate.into_iter().zip(allocation) {
+            ballot_states.extend(select_papers(
+                &mut self.rng,
+                candidate_ballot_states,
+                wanted,
+            ));
+        }
+        let papers: u32 = ballot_states.iter().map(|bs| bs.count).sum();
+        SurplusDistribution {
+            bundle_transactions: vec![(
+                BundleTransaction {
+                    ballot_states,
+                    transfer_value: whole(1),
+                    votes: whole(papers),
+                    papers,
+                    count: 0,
+                },
+                whole(1),
+            )],
+            votes: whole(papers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{ballot_state, bundle_transaction};
+
+    /// the number of papers transferred to each candidate from a surplus of 10, for a
+    /// candidate with 30 papers in their last parcel
+    fn transferred(seed: u64) -> HashMap<CandidateIndex, u32> {
+        let mut results = CountResults::new();
+        results.candidate_elected(CandidateIndex(0));
+        let bundle_transactions = vec![
+            bundle_transaction(vec![ballot_state(&[0, 1], 40)], whole(1), 1),
+            bundle_transaction(
+                vec![
+                    ballot_state(&[0, 1], 14),
+                    ballot_state(&[0, 2], 7),
+                    ballot_state(&[0, 3, 2], 2),
+                    ballot_state(&[0, 3], 4),
+                    ballot_state(&[0], 3),
+                ],
+                whole(1),
+                2,
+            ),
+        ];
+        let mut rules = NswLegislativeCouncil::new(seed);
+        let distribution = rules.distribute_surplus(&whole(10), bundle_transactions, &results);
+        assert_eq!(distribution.votes, whole(10));
+        let mut by_candidate = HashMap::new();
+        for (bundle_transaction, _) in distribution.bundle_transactions {
+            for ballot_state in bundle_transaction.ballot_states {
+                *by_candidate
+                    .entry(ballot_state.form.get(1).unwrap())
+                    .or_insert(0) += ballot_state.count;
+            }
+        }
+        by_candidate
+    }
+
+    #[test]
+    fn test_random_surplus_proportions() {
+        // 27 unexhausted papers: 14 for B, 7 for C and 6 for D. B is due 5.19 papers, C 2.59
+        // and D 2.22, so the paper left over goes to C
+        let by_candidate = transferred(1);
+        assert_eq!(by_candidate[&CandidateIndex(1)], 5);
+        assert_eq!(by_candidate[&CandidateIndex(2)], 3);
+        assert_eq!(by_candidate[&CandidateIndex(3)], 2);
+    }
+
+    #[test]
+    fn test_random_surplus_reproducible() {
+        let selected = |seed: u64| {
+            let mut rng = Prng::new(seed);
+            select_papers(
+                &mut rng,
+                vec![ballot_state(&[0], 10), ballot_state(&[1], 10)],
+                10,
+            )
+            .iter()
+            .map(|bs| bs.count)
+            .collect::<Vec<u32>>()
+        };
+        assert_eq!(selected(42), selected(42));
+        assert_eq!(selected(42).iter().sum::<u32>(), 10);
+        assert!((0..20).any(|seed| selected(seed) != selected(42)));
+    }
+
+    #[test]
+    fn test_random_surplus_input_order() {
+        // 39 forms which share a next preference, so that the papers selected depend upon the
+        // order in which the forms are walked
+        let selected = |reverse: bool| {
+            let mut results = CountResults::new();
+            results.candidate_elected(CandidateIndex(0));
+            let mut ballot_states: Vec<BallotState> =
+                (2..41).map(|c| ballot_state(&[0, 1, c], 2)).collect();
+            if reverse {
+                ballot_states.reverse();
+            }
+            let bundle_transactions = vec![bundle_transaction(ballot_states, whole(1), 1)];
+            let mut rules = NswLegislativeCouncil::new(42);
+            let distribution = rules.distribute_surplus(&whole(20), bundle_transactions, &results);
+            let mut selected: Vec<(Vec<CandidateIndex>, u32)> = distribution
+                .bundle_transactions
+                .into_iter()
+                .flat_map(|(bundle_transaction, _)| bundle_transaction.ballot_states)
+                .map(|bs| (bs.form.to_vec(), bs.count))
+                .collect();
+            selected.sort();
+            selected
+        };
+        assert_eq!(selected(false).iter().map(|(_, c)| c).sum::<u32>(), 20);
+        assert_eq!(selected(false), selected(true));
+    }
+
+    #[test]
+    fn test_prng_below() {
+        let mut rng = Prng::new(7);
+        for n in 1..100 {
+            assert!(rng.below(n) < n);
+        }
+    }
+}
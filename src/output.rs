@@ -1,18 +1,63 @@
-use configuration::{CountGroup, CountTask, Work};
+use aec::data::formalpreferences::{
+    ticket_label, BallotStatistics, FormalityReport, InformalReason, ParseError,
+};
+use configuration::{CountGroup, CountMethod, CountTask, Work};
 use defs::*;
-use engine::CountEngine;
-use std::collections::HashMap;
+use engine::{CountState, Counter};
+use house::two_candidate_preferred;
+use meek::DEFAULT_TOLERANCE;
+use num::rational::BigRational;
+use num::{ToPrimitive, Zero};
+use rules::{total, whole, QuotaFormula, RoundingPolicy, SurplusMethod};
+use senate2015::section_282_long_terms;
+use serde::Serializer;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 
+/// votes are written as a whole number where they are whole, as is always the case for
+/// the Senate, and otherwise as the nearest floating point number
+fn serialize_votes<S: Serializer>(votes: &BigRational, serializer: S) -> Result<S::Ok, S::Error> {
+    if votes.is_integer() {
+        if let Some(v) = votes.to_integer().to_u64() {
+            return serializer.serialize_u64(v);
+        }
+    }
+    let numer = votes.numer().to_f64().unwrap_or(std::f64::NAN);
+    let denom = votes.denom().to_f64().unwrap_or(std::f64::NAN);
+    serializer.serialize_f64(numer / denom)
+}
+
 #[derive(Serialize)]
 struct Parameters {
     house: String,
     name: String,
     description: String,
     state: String,
-    quota: u32,
+    #[serde(serialize_with = "serialize_votes")]
+    quota: BigRational,
+    quota_formula: String,
     vacancies: u32,
     total_papers: u32,
+    method: String,
+    rules: String,
+    excluded: Vec<String>,
+    bulk_exclusion: bool,
+    surplus: String,
+    rounding: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rounding_places: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tolerance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ticket_lots: Vec<TicketLotSummary>,
+}
+
+#[derive(Serialize)]
+struct TicketLotSummary {
+    group: String,
+    tickets: Vec<u32>,
 }
 
 #[derive(Serialize)]
@@ -22,11 +67,49 @@ struct Candidate {
     party: String,
 }
 
+#[derive(Serialize)]
+struct TieBreakSummary {
+    kind: String,
+    candidates: Vec<u32>,
+    pick: u32,
+    method: String,
+}
+
+#[derive(Serialize)]
+struct CandidateTotal {
+    #[serde(serialize_with = "serialize_votes")]
+    votes: BigRational,
+    papers: u32,
+}
+
+#[derive(Serialize)]
+struct Exhausted {
+    #[serde(serialize_with = "serialize_votes")]
+    votes: BigRational,
+    papers: u32,
+    #[serde(serialize_with = "serialize_votes")]
+    votes_in_count: BigRational,
+    papers_in_count: u32,
+}
+
+#[derive(Serialize)]
+struct LostByFraction {
+    #[serde(serialize_with = "serialize_votes")]
+    votes: BigRational,
+    #[serde(serialize_with = "serialize_votes")]
+    votes_in_count: BigRational,
+}
+
 #[derive(Serialize)]
 struct Count {
     number: usize,
     note: String,
     elected: Vec<u32>,
+    excluded: Vec<u32>,
+    tie_breaks: Vec<TieBreakSummary>,
+    totals: HashMap<u32, CandidateTotal>,
+    exhausted: Exhausted,
+    lost_by_fraction: LostByFraction,
 }
 
 #[derive(Serialize)]
@@ -41,6 +124,88 @@ struct Summary {
     excluded: Vec<ExclusionSummary>,
 }
 
+#[derive(Serialize)]
+struct Allocation {
+    order_of_election: Vec<u32>,
+    recount_order_of_election: Vec<u32>,
+    long_term_by_order_of_election: Vec<u32>,
+    long_term_by_recount: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct TwoCandidatePreferred {
+    candidate: u32,
+    #[serde(serialize_with = "serialize_votes")]
+    votes: BigRational,
+    percentage: f64,
+}
+
+#[derive(Serialize)]
+struct Informal {
+    blank: u32,
+    skipped_number: u32,
+    duplicate: u32,
+    too_few_preferences: u32,
+}
+
+#[derive(Serialize)]
+struct Formality {
+    formal_btl: u32,
+    formal_atl: u32,
+    btl_saved_by_atl: u32,
+    informal: Informal,
+}
+
+#[derive(Serialize)]
+struct SkippedLines {
+    count: usize,
+    lines: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct GroupStatistics {
+    group: usize,
+    ticket: String,
+    atl: u32,
+    btl: u32,
+}
+
+#[derive(Serialize)]
+struct Statistics {
+    papers: u32,
+    unique_forms: u32,
+    groups: Vec<GroupStatistics>,
+    ungrouped_btl: u32,
+    atl_depth: BTreeMap<usize, u32>,
+    btl_depth: BTreeMap<usize, u32>,
+    atl_marked: BTreeMap<usize, u32>,
+    btl_marked: BTreeMap<usize, u32>,
+    complete: u32,
+    complete_share: f64,
+}
+
+#[derive(Serialize)]
+struct Countback {
+    vacating: u32,
+    contesting: Vec<u32>,
+    elected: Vec<u32>,
+    counts: Vec<Count>,
+}
+
+#[derive(Serialize)]
+struct SeedOutcome {
+    seed: u64,
+    elected: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct Seeds {
+    outcomes: Vec<SeedOutcome>,
+    /// for each candidate elected under any seed, the number of seeds under which they
+    /// were elected
+    times_elected: BTreeMap<u32, usize>,
+}
+
 #[derive(Serialize)]
 struct Output {
     parameters: Option<Parameters>,
@@ -48,17 +213,28 @@ struct Output {
     parties: Option<HashMap<String, String>>,
     counts: Vec<Count>,
     summary: Summary,
+    allocation: Option<Allocation>,
+    two_candidate_preferred: Option<Vec<TwoCandidatePreferred>>,
+    countback: Option<Countback>,
+    seeds: Option<Seeds>,
+    formality: Option<Formality>,
+    skipped_lines: Option<SkippedLines>,
+    statistics: Option<Statistics>,
 }
 
 pub struct CountOutput {
     slug: String,
     output: Output,
+    elected_seen: usize,
+    excluded_seen: usize,
 }
 
 impl CountOutput {
     pub fn new(slug: &str) -> CountOutput {
         CountOutput {
             slug: slug.to_string(),
+            elected_seen: 0,
+            excluded_seen: 0,
             output: Output {
                 parameters: None,
                 candidates: None,
@@ -68,19 +244,94 @@ impl CountOutput {
                     elected: Vec::new(),
                     excluded: Vec::new(),
                 },
+                allocation: None,
+                two_candidate_preferred: None,
+                countback: None,
+                seeds: None,
+                formality: None,
+                skipped_lines: None,
+                statistics: None,
             },
         }
     }
 
-    pub fn set_parameters(&mut self, group: &CountGroup, task: &CountTask, engine: &CountEngine) {
+    pub fn set_parameters(&mut self, group: &CountGroup, task: &CountTask, engine: &dyn Counter) {
         self.output.parameters = Some(Parameters {
-            total_papers: engine.total_papers,
-            quota: engine.quota,
+            total_papers: engine.total_papers(),
+            quota: engine.quota().clone(),
+            quota_formula: match (task.method, task.quota) {
+                // recalculated at each count, from the votes not exhausted
+                (CountMethod::Meek, _) => "meek",
+                (CountMethod::House, _) => "absolute_majority",
+                (_, QuotaFormula::Droop) => "droop",
+                (_, QuotaFormula::Hare) => "hare",
+                (_, QuotaFormula::ExactDroop) => "exact_droop",
+                (_, QuotaFormula::HagenbachBischoff) => "hagenbach_bischoff",
+            }
+            .to_string(),
             house: group.house.clone(),
-            vacancies: engine.vacancies,
+            vacancies: engine.vacancies(),
             description: task.description.clone(),
             name: self.slug.clone(),
             state: task.dataset.clone(),
+            method: match task.method {
+                CountMethod::Senate => "senate",
+                CountMethod::SpecialRecount => "special_recount",
+                CountMethod::DoubleDissolution => "double_dissolution",
+                CountMethod::SenateGvt => "senate_gvt",
+                CountMethod::Meek => "meek",
+                CountMethod::Scotland2007 => "scotland2007",
+                CountMethod::NswLegislativeCouncil => "nsw_lc",
+                CountMethod::House => "house",
+            }
+            .to_string(),
+            rules: engine.rules_name().to_string(),
+            excluded: task.excluded.clone(),
+            bulk_exclusion: task.bulk_exclusion,
+            surplus: match (task.method, task.surplus) {
+                // surpluses pass on through the keep values
+                (CountMethod::Meek, _) => "meek",
+                // a single vacancy, filled by an absolute majority, leaves no surplus
+                (CountMethod::House, _) => "none",
+                (CountMethod::NswLegislativeCouncil, _) => "random_selection",
+                (CountMethod::Scotland2007, _) => "weighted_inclusive_gregory",
+                (_, SurplusMethod::UnweightedInclusiveGregory) => "unweighted_inclusive_gregory",
+                (_, SurplusMethod::WeightedInclusiveGregory) => "weighted_inclusive_gregory",
+                (_, SurplusMethod::LastParcel) => "last_parcel",
+            }
+            .to_string(),
+            rounding: match (task.method, task.rounding) {
+                (CountMethod::Meek, _) => "meek",
+                (CountMethod::House, _) => "none",
+                // whole papers are transferred at full value
+                (CountMethod::NswLegislativeCouncil, _) => "none",
+                (CountMethod::Scotland2007, _) => "truncate_transfer_value",
+                (_, RoundingPolicy::TruncateVotes) => "truncate_votes",
+                (_, RoundingPolicy::TruncateTransferValue(_)) => "truncate_transfer_value",
+                (_, RoundingPolicy::Fractional) => "fractional",
+            }
+            .to_string(),
+            rounding_places: match (task.method, task.rounding) {
+                (CountMethod::Meek, _)
+                | (CountMethod::House, _)
+                | (CountMethod::NswLegislativeCouncil, _) => None,
+                (CountMethod::Scotland2007, _) => Some(5),
+                (_, RoundingPolicy::TruncateTransferValue(places)) => Some(places),
+                _ => None,
+            },
+            tolerance: match task.method {
+                CountMethod::Meek => Some(task.tolerance.unwrap_or(DEFAULT_TOLERANCE)),
+                _ => None,
+            },
+            seed: task.seed,
+            ticket_lots: task
+                .ticket_lots
+                .iter()
+                .map(|lot| TicketLotSummary {
+                    group: lot.group.clone(),
+                    tickets: lot.tickets.clone(),
+                })
+                .collect(),
         });
     }
 
@@ -102,6 +353,213 @@ impl CountOutput {
         self.output.parties = Some(p);
     }
 
+    /// record the outcome of count `number`, which the engine has just completed
+    pub fn add_count(&mut self, number: usize, state: &CountState, engine: &dyn Counter) {
+        let elected = engine.get_elected();
+        let newly_elected = elected[self.elected_seen..]
+            .iter()
+            .map(|c| u32::from(c.0))
+            .collect();
+        self.elected_seen = elected.len();
+        let excluded = engine.get_excluded();
+        let newly_excluded = excluded[self.excluded_seen..]
+            .iter()
+            .map(|c| u32::from(c.0))
+            .collect();
+        self.excluded_seen = excluded.len();
+        let tie_breaks = engine
+            .get_tie_breaks()
+            .iter()
+            .filter(|tb| tb.count == number)
+            .map(|tb| TieBreakSummary {
+                kind: match tb.kind {
+                    TieKind::ElectionOrder => "election_order",
+                    TieKind::Election => "election",
+                    TieKind::Exclusion => "exclusion",
+                }
+                .to_string(),
+                candidates: tb.candidates.iter().map(|c| u32::from(c.0)).collect(),
+                pick: u32::from(tb.pick.0),
+                method: match tb.method {
+                    TieBreakMethod::PreviousCount => "previous_count",
+                    TieBreakMethod::Resolution => "resolution",
+                    TieBreakMethod::Decision => "decision",
+                }
+                .to_string(),
+            })
+            .collect();
+        let totals = state
+            .votes_per_candidate
+            .iter()
+            .map(|(candidate, votes)| {
+                (
+                    u32::from(candidate.0),
+                    CandidateTotal {
+                        votes: votes.clone(),
+                        papers: state.papers_per_candidate[candidate],
+                    },
+                )
+            })
+            .collect();
+        self.output.counts.push(Count {
+            number,
+            note: String::new(),
+            elected: newly_elected,
+            excluded: newly_excluded,
+            tie_breaks,
+            totals,
+            exhausted: Exhausted {
+                votes: state.votes_exhausted.clone(),
+                papers: state.papers_exhausted,
+                votes_in_count: state.votes_exhausted_in_count.clone(),
+                papers_in_count: state.papers_exhausted_in_count,
+            },
+            lost_by_fraction: LostByFraction {
+                votes: state.votes_lost_by_fraction.clone(),
+                votes_in_count: state.votes_lost_by_fraction_in_count.clone(),
+            },
+        });
+    }
+
+    /// record the allocation of long and short terms following a double dissolution, both by
+    /// order of election in the full count and by the section 282 recount
+    pub fn set_allocation(
+        &mut self,
+        order_of_election: &[CandidateIndex],
+        recount_order_of_election: &[CandidateIndex],
+    ) {
+        let ids = |candidates: &[CandidateIndex]| -> Vec<u32> {
+            candidates.iter().map(|c| u32::from(c.0)).collect()
+        };
+        let long_terms = order_of_election.len() / 2;
+        self.output.allocation = Some(Allocation {
+            order_of_election: ids(order_of_election),
+            recount_order_of_election: ids(recount_order_of_election),
+            long_term_by_order_of_election: ids(&order_of_election[..long_terms]),
+            long_term_by_recount: ids(&section_282_long_terms(
+                order_of_election,
+                recount_order_of_election,
+            )),
+        });
+    }
+
+    /// record the two-candidate-preferred result of a count of the House of Representatives,
+    /// from the final count
+    pub fn set_two_candidate_preferred(&mut self, final_state: &CountState) {
+        let result = two_candidate_preferred(final_state);
+        let total = total(result.iter().map(|(_, votes)| votes));
+        self.output.two_candidate_preferred = Some(
+            result
+                .into_iter()
+                .map(|(candidate, votes)| {
+                    let percentage = if total.is_zero() {
+                        0.0
+                    } else {
+                        let share = &votes * whole(100) / &total;
+                        share.numer().to_f64().unwrap_or(std::f64::NAN)
+                            / share.denom().to_f64().unwrap_or(std::f64::NAN)
+                    };
+                    TwoCandidatePreferred {
+                        candidate: u32::from(candidate.0),
+                        votes,
+                        percentage,
+                    }
+                })
+                .collect(),
+        );
+    }
+
+    /// record a countback to fill the vacancy left by `vacating`, with the counts recorded
+    /// in `countback`
+    pub fn set_countback(
+        &mut self,
+        vacating: CandidateIndex,
+        contesting: &[CandidateIndex],
+        elected: &[CandidateIndex],
+        countback: CountOutput,
+    ) {
+        let ids = |candidates: &[CandidateIndex]| -> Vec<u32> {
+            candidates.iter().map(|c| u32::from(c.0)).collect()
+        };
+        self.output.countback = Some(Countback {
+            vacating: u32::from(vacating.0),
+            contesting: ids(contesting),
+            elected: ids(elected),
+            counts: countback.output.counts,
+        });
+    }
+
+    /// record the candidates elected when the count is repeated with each of `outcomes`
+    /// seeds, and the number of seeds under which each candidate was elected
+    pub fn set_seed_outcomes(&mut self, outcomes: &[(u64, Vec<CandidateIndex>)]) {
+        let mut times_elected = BTreeMap::new();
+        for (_, elected) in outcomes {
+            for candidate in elected {
+                *times_elected.entry(u32::from(candidate.0)).or_insert(0) += 1;
+            }
+        }
+        self.output.seeds = Some(Seeds {
+            outcomes: outcomes
+                .iter()
+                .map(|(seed, elected)| SeedOutcome {
+                    seed: *seed,
+                    elected: elected.iter().map(|c| u32::from(c.0)).collect(),
+                })
+                .collect(),
+            times_elected,
+        });
+    }
+
+    /// record the formality of the papers in the formal preferences file
+    pub fn set_formality(&mut self, report: &FormalityReport) {
+        self.output.formality = Some(Formality {
+            formal_btl: report.btl,
+            formal_atl: report.atl,
+            btl_saved_by_atl: report.btl_saved_by_atl,
+            informal: Informal {
+                blank: report.informal_for(InformalReason::Blank),
+                skipped_number: report.informal_for(InformalReason::SkippedNumber),
+                duplicate: report.informal_for(InformalReason::Duplicate),
+                too_few_preferences: report.informal_for(InformalReason::TooFewPreferences),
+            },
+        });
+    }
+
+    /// record the lines of the formal preferences file which were skipped, as they couldn't
+    /// be parsed
+    pub fn set_skipped_lines(&mut self, skipped: &[ParseError]) {
+        self.output.skipped_lines = Some(SkippedLines {
+            count: skipped.len(),
+            lines: skipped.iter().filter_map(|error| error.line).collect(),
+        });
+    }
+
+    /// record the statistics on how the formal papers were marked
+    pub fn set_statistics(&mut self, statistics: &BallotStatistics) {
+        self.output.statistics = Some(Statistics {
+            papers: statistics.papers,
+            unique_forms: statistics.unique_forms,
+            groups: statistics
+                .groups
+                .iter()
+                .enumerate()
+                .map(|(idx, group)| GroupStatistics {
+                    group: idx,
+                    ticket: ticket_label(idx),
+                    atl: group.atl,
+                    btl: group.btl,
+                })
+                .collect(),
+            ungrouped_btl: statistics.ungrouped_btl,
+            atl_depth: statistics.atl_depth.clone(),
+            btl_depth: statistics.btl_depth.clone(),
+            atl_marked: statistics.atl_marked.clone(),
+            btl_marked: statistics.btl_marked.clone(),
+            complete: statistics.complete,
+            complete_share: statistics.complete_share(),
+        });
+    }
+
     pub fn close(&self) {
         let output_file = format!("angular/data/{}.json", self.slug);
         let fd = File::create(output_file).unwrap();
@@ -0,0 +1,597 @@
+/*
+ * the rules under which a count is conducted
+ */
+
+use defs::*;
+use num::rational::BigRational;
+use num::{BigInt, FromPrimitive, Zero};
+
+/// a whole number of votes (or papers), as a rational
+pub fn whole(n: u32) -> BigRational {
+    BigRational::from_integer(BigInt::from_u32(n).unwrap())
+}
+
+/// the total of a number of rationals
+pub fn total<'a, I: Iterator<Item = &'a BigRational>>(values: I) -> BigRational {
+    values.fold(BigRational::zero(), |acc, v| acc + v)
+}
+
+/// truncate to `places` decimal places
+pub fn truncate_places(value: &BigRational, places: u32) -> BigRational {
+    let scale = whole(10u32.pow(places));
+    (value * &scale).trunc() / scale
+}
+
+/// the bundle transactions to be distributed from the surplus of an elected candidate
+pub struct SurplusDistribution {
+    /// the bundle transactions, each paired with the transfer value at which it is distributed
+    pub bundle_transactions: Vec<(BundleTransaction, BigRational)>,
+    /// the votes leaving the elected candidate; ordinarily, the whole of the surplus
+    pub votes: BigRational,
+}
+
+/// the policy under which a count is conducted: the quota, the determination and application
+/// of transfer values, the distribution of surpluses, the exclusion of candidates, and the
+/// procedures which bring the count to an end. `CountEngine` takes care of the mechanics of
+/// the count, and defers to these rules wherever jurisdictions differ.
+pub trait CountingRules {
+    /// the name of the rules, for reporting
+    fn name(&self) -> &'static str;
+
+    /// determine the quota for election
+    fn quota(&self, total_papers: u32, vacancies: u32) -> BigRational;
+
+    /// does a candidate with `votes` have a quota, and so is elected?
+    fn has_quota(&self, votes: &BigRational, quota: &BigRational) -> bool;
+
+    /// the votes represented by `papers` ballot papers at `transfer_value`
+    fn apply_transfer_value(&self, transfer_value: &BigRational, papers: u32) -> BigRational;
+
+    /// the surplus of an elected candidate, being their `votes` in excess of the `quota`
+    fn surplus(&self, votes: &BigRational, quota: &BigRational) -> BigRational {
+        if votes > quota {
+            votes - quota
+        } else {
+            BigRational::zero()
+        }
+    }
+
+    /// determine the bundle transactions to be distributed from the surplus of an elected
+    /// candidate, who holds `bundle_transactions` in the order they were received. papers
+    /// which are not distributed remain with the elected candidate. `results` gives the
+    /// candidates elected and excluded so far, including the elected candidate
+    fn distribute_surplus(
+        &mut self,
+        surplus: &BigRational,
+        bundle_transactions: Vec<BundleTransaction>,
+        results: &CountResults,
+    ) -> SurplusDistribution;
+
+    /// may candidates be excluded in bulk, when they can't possibly be elected?
+    fn bulk_exclusion(&self) -> bool {
+        false
+    }
+
+    /// once all surpluses have been distributed, and only two candidates remain for the last
+    /// vacancy, is the candidate with more votes elected without further distribution?
+    fn elect_last_two(&self) -> bool {
+        true
+    }
+
+    /// are the continuing candidates elected as soon as there are only as many of them as
+    /// there are remaining vacancies?
+    fn elect_remaining(&self) -> bool {
+        true
+    }
+
+    /// are ties broken by reference to the earliest count at which the candidates' votes
+    /// differed, rather than the most recent?
+    fn tie_break_earliest_count(&self) -> bool {
+        false
+    }
+
+    /// are all the papers of an excluded candidate distributed in a single count, each at
+    /// the value at which it was received, rather than in a count for each transfer value?
+    fn exclusion_in_one_count(&self) -> bool {
+        false
+    }
+
+    /// is the largest surplus awaiting distribution distributed first, rather than the
+    /// surplus of the candidate elected first?
+    fn largest_surplus_first(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// the method by which the surplus of an elected candidate is distributed
+pub enum SurplusMethod {
+    /// every paper held by the candidate is distributed at the same transfer value: the
+    /// surplus divided by the number of papers, regardless of the value at which each
+    /// paper was received. as used for the Senate
+    #[default]
+    UnweightedInclusiveGregory,
+    /// every paper held by the candidate is distributed, at the value at which it was
+    /// received multiplied by the surplus divided by the candidate's votes. as used in
+    /// Western Australia and Victoria
+    WeightedInclusiveGregory,
+    /// only the last parcel of papers received by the candidate, which elected them, is
+    /// distributed: at the surplus divided by the number of unexhausted papers in the
+    /// parcel, or at the value at which the parcel was received if that is lower. exhausted
+    /// papers are set aside with the candidate. as used for Hare-Clark elections in Tasmania
+    /// and the ACT
+    LastParcel,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// the rounding applied to transfer values, and to the votes they give
+pub enum RoundingPolicy {
+    /// transfer values are kept exactly, and the votes of each bundle of papers, and each
+    /// surplus, are rounded down to a whole number: fractional votes are lost at each
+    /// transfer. as used for the Senate, section 273(9)(b)
+    #[default]
+    TruncateVotes,
+    /// transfer values are truncated to the given number of decimal places, and votes are
+    /// otherwise kept exactly
+    TruncateTransferValue(u32),
+    /// transfer values and votes are kept exactly, so that no votes are lost by fraction
+    Fractional,
+}
+
+impl RoundingPolicy {
+    /// the transfer value actually applied, given the value calculated
+    pub fn transfer_value(self, transfer_value: BigRational) -> BigRational {
+        match self {
+            RoundingPolicy::TruncateTransferValue(places) => {
+                truncate_places(&transfer_value, places)
+            }
+            RoundingPolicy::TruncateVotes | RoundingPolicy::Fractional => transfer_value,
+        }
+    }
+
+    /// round a number of votes
+    pub fn votes(self, votes: BigRational) -> BigRational {
+        match self {
+            RoundingPolicy::TruncateVotes => votes.floor(),
+            RoundingPolicy::TruncateTransferValue(_) | RoundingPolicy::Fractional => votes,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// the formula by which the quota is determined, from the total number of papers `T` and
+/// the number of vacancies `v`
+pub enum QuotaFormula {
+    /// `floor(T / (v + 1)) + 1`, which a candidate must exceed to be elected
+    #[default]
+    Droop,
+    /// `T / v`, which a candidate must reach to be elected
+    Hare,
+    /// the exact (fractional) Droop quota of Newland and Britton, `T / (v + 1)`, which a
+    /// candidate must exceed to be elected
+    ExactDroop,
+    /// the Hagenbach-Bischoff quota `T / (v + 1)`, which a candidate must reach to be elected
+    HagenbachBischoff,
+}
+
+impl QuotaFormula {
+    pub fn quota(self, total_papers: u32, vacancies: u32) -> BigRational {
+        match self {
+            QuotaFormula::Droop => whole((total_papers / (vacancies + 1)) + 1),
+            QuotaFormula::Hare => whole(total_papers) / whole(vacancies),
+            QuotaFormula::ExactDroop | QuotaFormula::HagenbachBischoff => {
+                whole(total_papers) / whole(vacancies + 1)
+            }
+        }
+    }
+
+    /// does a candidate with `votes` have a quota?
+    pub fn reached(self, votes: &BigRational, quota: &BigRational) -> bool {
+        match self {
+            QuotaFormula::Droop | QuotaFormula::ExactDroop => votes > quota,
+            QuotaFormula::Hare | QuotaFormula::HagenbachBischoff => votes >= quota,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// the rules for the Australian Senate, following the 2016 amendments to the
+/// Commonwealth Electoral Act 1918: a Droop quota, and surpluses distributed under the
+/// unweighted inclusive Gregory method, with fractional votes lost at each transfer.
+/// the surplus method, the quota and the rounding may be varied, so that counts can be
+/// compared
+pub struct Senate2016 {
+    /// exclude candidates in bulk, under section 273(13A)
+    pub bulk_exclusion: bool,
+    pub surplus: SurplusMethod,
+    pub quota: QuotaFormula,
+    pub rounding: RoundingPolicy,
+}
+
+impl Senate2016 {
+    pub fn new() -> Senate2016 {
+        Senate2016::default()
+    }
+}
+
+impl CountingRules for Senate2016 {
+    fn name(&self) -> &'static str {
+        "senate2016"
+    }
+
+    fn quota(&self, total_papers: u32, vacancies: u32) -> BigRational {
+        self.quota.quota(total_papers, vacancies)
+    }
+
+    fn has_quota(&self, votes: &BigRational, quota: &BigRational) -> bool {
+        self.quota.reached(votes, quota)
+    }
+
+    /// ordinarily, rounds down to a whole number of votes
+    fn apply_transfer_value(&self, transfer_value: &BigRational, papers: u32) -> BigRational {
+        self.rounding.votes(transfer_value * whole(papers))
+    }
+
+    fn surplus(&self, votes: &BigRational, quota: &BigRational) -> BigRational {
+        if votes > quota {
+            self.rounding.votes(votes - quota)
+        } else {
+            BigRational::zero()
+        }
+    }
+
+    /// where transfer values are truncated, the part of the surplus lost to the truncation
+    /// is lost by fraction
+    fn distribute_surplus(
+        &mut self,
+        surplus: &BigRational,
+        bundle_transactions: Vec<BundleTransaction>,
+        results: &CountResults,
+    ) -> SurplusDistribution {
+        match self.surplus {
+            SurplusMethod::UnweightedInclusiveGregory => {
+                // section 273(9)
+                let papers: u32 = bundle_transactions.iter().map(|bt| bt.papers).sum();
+                let transfer_value = self.rounding.transfer_value(surplus / whole(papers));
+                SurplusDistribution {
+                    bundle_transactions: bundle_transactions
+                        .into_iter()
+                        .map(|bt| (bt, transfer_value.clone()))
+                        .collect(),
+                    votes: surplus.clone(),
+                }
+            }
+            SurplusMethod::WeightedInclusiveGregory => {
+                let votes = total(bundle_transactions.iter().map(|bt| &bt.votes));
+                let factor = surplus / votes;
+                SurplusDistribution {
+                    bundle_transactions: bundle_transactions
+                        .into_iter()
+                        .map(|bt| {
+                            let transfer_value =
+                                self.rounding.transfer_value(&bt.transfer_value * &factor);
+                            (bt, transfer_value)
+                        })
+                        .collect(),
+                    votes: surplus.clone(),
+                }
+            }
+            SurplusMethod::LastParcel => {
+                // only the papers in the last parcel with a further preference are distributed
+                let last = bundle_transactions.iter().map(|bt| bt.count).max();
+                let last_parcel: Vec<BundleTransaction> = bundle_transactions
+                    .into_iter()
+                    .filter(|bt| Some(bt.count) == last)
+                    .map(|bt| {
+                        let ballot_states: Vec<BallotState> = bt
+                            .ballot_states
+                            .into_iter()
+                            .filter(|bs| bs.has_next_preference(results))
+                            .collect();
+                        let papers = ballot_states.iter().map(|bs| bs.count).sum();
+                        BundleTransaction {
+                            ballot_states,
+                            votes: self.apply_transfer_value(&bt.transfer_value, papers),
+                            papers,
+                            ..bt
+                        }
+                    })
+                    .filter(|bt| bt.papers > 0)
+                    .collect();
+                if last_parcel.is_empty() {
+                    return SurplusDistribution {
+                        bundle_transactions: Vec::new(),
+                        votes: BigRational::zero(),
+                    };
+                }
+                let papers: u32 = last_parcel.iter().map(|bt| bt.papers).sum();
+                let transfer_value = self.rounding.transfer_value(surplus / whole(papers));
+                let distribution: Vec<(BundleTransaction, BigRational)> = last_parcel
+                    .into_iter()
+                    .map(|bt| {
+                        let transfer_value = if transfer_value > bt.transfer_value {
+                            bt.transfer_value.clone()
+                        } else {
+                            transfer_value.clone()
+                        };
+                        (bt, transfer_value)
+                    })
+                    .collect();
+                // where the transfer value is limited to that of the parcel, less than the
+                // whole surplus leaves the candidate
+                let values: Vec<BigRational> = distribution
+                    .iter()
+                    .map(|(bt, tv)| tv * whole(bt.papers))
+                    .collect();
+                SurplusDistribution {
+                    bundle_transactions: distribution,
+                    votes: total(values.iter()),
+                }
+            }
+        }
+    }
+
+    fn bulk_exclusion(&self) -> bool {
+        self.bulk_exclusion
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// the rules for Scottish local government elections, under the Scottish Local Government
+/// Elections Order 2007: a Droop quota, reached when a candidate's votes are equal to it,
+/// and surpluses distributed under the weighted inclusive Gregory method, at transfer values
+/// truncated to five decimal places. votes are otherwise kept exactly. the largest surplus
+/// is distributed first, the papers of an excluded candidate are transferred in a single
+/// stage, and ties are broken by reference to the earliest stage at which the candidates'
+/// votes differed
+pub struct Scotland2007;
+
+impl CountingRules for Scotland2007 {
+    fn name(&self) -> &'static str {
+        "scotland2007"
+    }
+
+    fn quota(&self, total_papers: u32, vacancies: u32) -> BigRational {
+        whole((total_papers / (vacancies + 1)) + 1)
+    }
+
+    fn has_quota(&self, votes: &BigRational, quota: &BigRational) -> bool {
+        votes >= quota
+    }
+
+    /// transfer values have five decimal places, so there is no further rounding
+    fn apply_transfer_value(&self, transfer_value: &BigRational, papers: u32) -> BigRational {
+        transfer_value * whole(papers)
+    }
+
+    /// each paper is transferred at the surplus multiplied by the value of the paper, divided
+    /// by the candidate's votes, truncated to five decimal places. the part of the surplus
+    /// lost to the truncation is lost by fraction
+    fn distribute_surplus(
+        &mut self,
+        surplus: &BigRational,
+        bundle_transactions: Vec<BundleTransaction>,
+        _results: &CountResults,
+    ) -> SurplusDistribution {
+        let votes = total(bundle_transactions.iter().map(|bt| &bt.votes));
+        SurplusDistribution {
+            bundle_transactions: bundle_transactions
+                .into_iter()
+                .map(|bt| {
+                    let transfer_value =
+                        truncate_places(&(&bt.transfer_value * surplus / &votes), 5);
+                    (bt, transfer_value)
+                })
+                .collect(),
+            votes: surplus.clone(),
+        }
+    }
+
+    /// the count continues until only as many candidates remain as there are vacancies
+    fn elect_last_two(&self) -> bool {
+        false
+    }
+
+    fn tie_break_earliest_count(&self) -> bool {
+        true
+    }
+
+    fn exclusion_in_one_count(&self) -> bool {
+        true
+    }
+
+    fn largest_surplus_first(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{ballot_state, bundle_transaction};
+
+    #[test]
+    fn test_apply_transfer_value() {
+        let rules = Senate2016::new();
+        let a = whole(1) / whole(3);
+        assert_eq!(rules.apply_transfer_value(&a, 0), whole(0));
+        assert_eq!(rules.apply_transfer_value(&a, 1), whole(0));
+        assert_eq!(rules.apply_transfer_value(&a, 2), whole(0));
+        assert_eq!(rules.apply_transfer_value(&a, 3), whole(1));
+        assert_eq!(rules.apply_transfer_value(&a, 4), whole(1));
+        assert_eq!(rules.apply_transfer_value(&a, 5), whole(1));
+        assert_eq!(rules.apply_transfer_value(&a, 6), whole(2));
+    }
+
+    /// a parcel of `papers` papers, each with a further preference
+    fn parcel(transfer_value: BigRational, papers: u32, count: usize) -> BundleTransaction {
+        bundle_transaction(vec![ballot_state(&[0, 1], papers)], transfer_value, count)
+    }
+
+    fn surplus_distribution(
+        rules: &mut Senate2016,
+        surplus: u32,
+    ) -> (Vec<BigRational>, BigRational) {
+        // a candidate holding 4 votes on 4 papers at full value, and 2 votes on 4 papers
+        // received at a transfer value of one half
+        let bundle_transactions = vec![parcel(whole(1), 4, 1), parcel(whole(1) / whole(2), 4, 3)];
+        let distribution =
+            rules.distribute_surplus(&whole(surplus), bundle_transactions, &CountResults::new());
+        (
+            distribution
+                .bundle_transactions
+                .into_iter()
+                .map(|(_, tv)| tv)
+                .collect(),
+            distribution.votes,
+        )
+    }
+
+    fn surplus_transfer_values(rules: &mut Senate2016) -> Vec<BigRational> {
+        surplus_distribution(rules, 3).0
+    }
+
+    #[test]
+    fn test_unweighted_surplus() {
+        let mut rules = Senate2016::new();
+        let tv = whole(3) / whole(8);
+        assert_eq!(surplus_transfer_values(&mut rules), vec![tv.clone(), tv]);
+    }
+
+    #[test]
+    fn test_weighted_surplus() {
+        let mut rules = Senate2016 {
+            surplus: SurplusMethod::WeightedInclusiveGregory,
+            ..Senate2016::default()
+        };
+        assert_eq!(
+            surplus_transfer_values(&mut rules),
+            vec![whole(1) / whole(2), whole(1) / whole(4)]
+        );
+    }
+
+    #[test]
+    fn test_last_parcel_surplus() {
+        let mut rules = Senate2016 {
+            surplus: SurplusMethod::LastParcel,
+            ..Senate2016::default()
+        };
+        // only the parcel received at the third count is distributed
+        assert_eq!(
+            surplus_distribution(&mut rules, 1),
+            (vec![whole(1) / whole(4)], whole(1))
+        );
+        // the transfer value is limited to that of the parcel
+        assert_eq!(
+            surplus_distribution(&mut rules, 3),
+            (vec![whole(1) / whole(2)], whole(2))
+        );
+    }
+
+    #[test]
+    fn test_last_parcel_exhausted_papers() {
+        let mut rules = Senate2016 {
+            surplus: SurplusMethod::LastParcel,
+            ..Senate2016::default()
+        };
+        // the last parcel holds four papers, two of which have no further preference: the
+        // surplus is divided between the other two
+        let last_parcel = bundle_transaction(
+            vec![ballot_state(&[0, 1], 2), ballot_state(&[0, 2], 2)],
+            whole(1),
+            2,
+        );
+        let bundle_transactions = vec![parcel(whole(1), 4, 1), last_parcel];
+        let mut results = CountResults::new();
+        results.candidate_elected(CandidateIndex(0));
+        results.candidate_excluded(CandidateIndex(2));
+        let distribution = rules.distribute_surplus(&whole(1), bundle_transactions, &results);
+        assert_eq!(distribution.votes, whole(1));
+        assert_eq!(distribution.bundle_transactions.len(), 1);
+        let (ref bt, ref transfer_value) = distribution.bundle_transactions[0];
+        assert_eq!(*transfer_value, whole(1) / whole(2));
+        assert_eq!(bt.papers, 2);
+        assert_eq!(
+            bt.ballot_states[0].form.to_vec(),
+            vec![CandidateIndex(0), CandidateIndex(1)]
+        );
+    }
+
+    #[test]
+    fn test_senate_quota() {
+        assert_eq!(Senate2016::new().quota(100, 6), whole(15));
+        assert_eq!(Senate2016::new().quota(98, 6), whole(15));
+        assert_eq!(Senate2016::new().quota(99, 1), whole(50));
+    }
+
+    #[test]
+    fn test_scotland_surplus() {
+        // a candidate with 11 votes, 7 of them on papers at full value and 4 on 8 papers at
+        // one half, has a surplus of 2; each paper goes at a value truncated to 5 places
+        let bundle_transactions = vec![parcel(whole(1), 7, 1), parcel(whole(1) / whole(2), 8, 2)];
+        let distribution =
+            Scotland2007.distribute_surplus(&whole(2), bundle_transactions, &CountResults::new());
+        let transfer_values: Vec<BigRational> = distribution
+            .bundle_transactions
+            .into_iter()
+            .map(|(_, tv)| tv)
+            .collect();
+        assert_eq!(
+            transfer_values,
+            vec![
+                whole(18_181) / whole(100_000),
+                whole(9_090) / whole(100_000)
+            ]
+        );
+        assert_eq!(distribution.votes, whole(2));
+    }
+
+    #[test]
+    fn test_truncate_places() {
+        let third = whole(1) / whole(3);
+        assert_eq!(truncate_places(&third, 5), whole(33_333) / whole(100_000));
+        assert_eq!(truncate_places(&whole(2), 5), whole(2));
+    }
+
+    #[test]
+    fn test_quota_formulas() {
+        assert_eq!(QuotaFormula::Hare.quota(100, 6), whole(50) / whole(3));
+        assert_eq!(
+            QuotaFormula::ExactDroop.quota(100, 6),
+            whole(100) / whole(7)
+        );
+        assert_eq!(
+            QuotaFormula::HagenbachBischoff.quota(100, 6),
+            whole(100) / whole(7)
+        );
+        let quota = whole(100) / whole(7);
+        assert!(!QuotaFormula::ExactDroop.reached(&quota, &quota));
+        assert!(QuotaFormula::HagenbachBischoff.reached(&quota, &quota));
+    }
+
+    #[test]
+    fn test_rounding_policies() {
+        let tv = whole(1) / whole(3);
+        let rules = |rounding| Senate2016 {
+            rounding,
+            ..Senate2016::default()
+        };
+        let truncated = rules(RoundingPolicy::TruncateTransferValue(2));
+        assert_eq!(truncated.apply_transfer_value(&tv, 5), whole(5) / whole(3));
+        let fractional = rules(RoundingPolicy::Fractional);
+        assert_eq!(fractional.apply_transfer_value(&tv, 5), whole(5) / whole(3));
+        assert_eq!(
+            fractional.surplus(&(whole(21) / whole(2)), &whole(10)),
+            whole(1) / whole(2)
+        );
+        assert_eq!(
+            Senate2016::new().surplus(&(whole(21) / whole(2)), &whole(10)),
+            whole(0)
+        );
+        // the transfer value of 3/8 is truncated to 0.37
+        assert_eq!(
+            surplus_transfer_values(&mut rules(RoundingPolicy::TruncateTransferValue(2))),
+            vec![whole(37) / whole(100), whole(37) / whole(100)]
+        );
+    }
+}
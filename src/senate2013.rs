@@ -0,0 +1,201 @@
+use aec;
+use defs::*;
+use std::collections::HashMap;
+
+/// the group voting tickets lodged by each group, by group letter, as used in Senate elections
+/// before the 2016 reforms; along with the AEC's candidate ids, as used in other data files
+pub struct GroupVotingTickets {
+    pub tickets: HashMap<String, Vec<Vec<CandidateIndex>>>,
+    pub candidate_ids: HashMap<u32, CandidateIndex>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+/// the lot drawn where a group's papers don't divide equally between its group voting
+/// tickets: `tickets`, numbered from one in the order lodged, each receive one of the
+/// papers left over
+pub struct TicketLot {
+    pub group: String,
+    pub tickets: Vec<u32>,
+}
+
+pub fn load_group_voting_tickets(
+    rows: &[aec::data::gvt::AECGroupVotingTicketRow],
+    candidates: &CandidateData,
+) -> Result<GroupVotingTickets, String> {
+    let mut candidate_ids = HashMap::new();
+    let mut preferences: HashMap<(String, u32), Vec<(u32, CandidateIndex)>> = HashMap::new();
+    for row in rows {
+        let name = format!("{}, {}", row.surname, row.given_nm);
+        let candidate = match candidates.find_by_name(&name) {
+            Some(candidate) => candidate,
+            None => {
+                return Err(format!(
+                    "unknown candidate on group voting ticket: {}",
+                    name
+                ))
+            }
+        };
+        candidate_ids.insert(row.candidate_id, candidate);
+        preferences
+            .entry((row.owner_ticket.clone(), row.ticket_no))
+            .or_insert_with(Vec::new)
+            .push((row.preference_no, candidate));
+    }
+
+    let mut keys: Vec<(String, u32)> = preferences.keys().cloned().collect();
+    keys.sort();
+    let mut tickets = HashMap::new();
+    for key in keys {
+        let mut ticket = preferences.remove(&key).unwrap();
+        ticket.sort();
+        tickets
+            .entry(key.0)
+            .or_insert_with(Vec::new)
+            .push(ticket.into_iter().map(|(_, c)| c).collect());
+    }
+    Ok(GroupVotingTickets {
+        tickets,
+        candidate_ids,
+    })
+}
+
+/// the tickets of `group`, of which there are `tickets`, which receive the `remainder`
+/// papers left over when its papers are divided between them, as drawn by lot
+fn drawn_tickets<'a>(
+    lots: &'a [TicketLot],
+    group: &str,
+    tickets: u32,
+    remainder: u32,
+) -> Result<&'a [u32], String> {
+    let lot = match lots.iter().find(|lot| lot.group == group) {
+        Some(lot) => lot,
+        None => {
+            return Err(format!(
+                "group {}: {} papers left over between {} tickets, and no ticket lot supplied",
+                group, remainder, tickets
+            ))
+        }
+    };
+    let mut drawn = lot.tickets.clone();
+    drawn.sort();
+    drawn.dedup();
+    if drawn.len() != lot.tickets.len()
+        || lot.tickets.len() != remainder as usize
+        || drawn.iter().any(|t| *t < 1 || *t > tickets)
+    {
+        return Err(format!(
+            "group {}: the ticket lot must draw {} distinct tickets, numbered from 1 to {}",
+            group, remainder, tickets
+        ));
+    }
+    Ok(&lot.tickets)
+}
+
+/// expand the papers marked above the line for each group into ballot states following the
+/// group's tickets. where a group has lodged more than one ticket, its papers are divided
+/// equally between them, and any papers left over are allocated to tickets chosen by lot
+/// (Schedule 3 to the Act, as it stood before the 2016 reforms); the lot is taken from
+/// `lots`
+pub fn ticket_ballot_states(
+    gvt: &GroupVotingTickets,
+    ticket_votes: &[(String, u32)],
+    lots: &[TicketLot],
+) -> Result<Vec<BallotState>, String> {
+    let mut ballot_states = Vec::new();
+    for (group, votes) in ticket_votes {
+        if *votes == 0 {
+            continue;
+        }
+        let tickets = match gvt.tickets.get(group) {
+            Some(tickets) => tickets,
+            None => return Err(format!("no group voting ticket lodged for group {}", group)),
+        };
+        let n = tickets.len() as u32;
+        let drawn = match votes % n {
+            0 => &[][..],
+            remainder => drawn_tickets(lots, group, n, remainder)?,
+        };
+        for (idx, ticket) in tickets.iter().enumerate() {
+            let count = votes / n
+                + if drawn.contains(&(idx as u32 + 1)) {
+                    1
+                } else {
+                    0
+                };
+            if count > 0 {
+                ballot_states.push(BallotState {
+                    form: ticket.iter().cloned().collect(),
+                    count,
+                    active_preference: 0,
+                });
+            }
+        }
+    }
+    Ok(ballot_states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tickets() -> GroupVotingTickets {
+        let mut tickets = HashMap::new();
+        tickets.insert(
+            "A".to_string(),
+            vec![vec![
+                CandidateIndex(0),
+                CandidateIndex(1),
+                CandidateIndex(2),
+            ]],
+        );
+        tickets.insert(
+            "B".to_string(),
+            vec![
+                vec![CandidateIndex(2), CandidateIndex(0), CandidateIndex(1)],
+                vec![CandidateIndex(2), CandidateIndex(1), CandidateIndex(0)],
+            ],
+        );
+        GroupVotingTickets {
+            tickets,
+            candidate_ids: HashMap::new(),
+        }
+    }
+
+    fn ticket_lot(tickets: &[u32]) -> Vec<TicketLot> {
+        vec![TicketLot {
+            group: "B".to_string(),
+            tickets: tickets.to_vec(),
+        }]
+    }
+
+    #[test]
+    fn split_ticket_votes() {
+        // the paper left over from group B goes to its second ticket, drawn by lot
+        let votes = vec![("A".to_string(), 10), ("B".to_string(), 7)];
+        let ballot_states = ticket_ballot_states(&tickets(), &votes, &ticket_lot(&[2])).unwrap();
+        let counts: Vec<u32> = ballot_states.iter().map(|bs| bs.count).collect();
+        assert_eq!(counts, vec![10, 3, 4]);
+        assert_eq!(
+            ballot_states[2].form.to_vec(),
+            vec![CandidateIndex(2), CandidateIndex(1), CandidateIndex(0)]
+        );
+    }
+
+    #[test]
+    fn split_ticket_votes_needs_lot() {
+        let votes = vec![("B".to_string(), 7)];
+        assert!(ticket_ballot_states(&tickets(), &votes, &[]).is_err());
+        assert!(ticket_ballot_states(&tickets(), &votes, &ticket_lot(&[3])).is_err());
+        assert!(ticket_ballot_states(&tickets(), &votes, &ticket_lot(&[1, 2])).is_err());
+        // no lot is needed where the papers divide equally
+        let votes = vec![("B".to_string(), 8)];
+        let ballot_states = ticket_ballot_states(&tickets(), &votes, &[]);
+        assert_eq!(ballot_states.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn unknown_group() {
+        let votes = vec![("C".to_string(), 10)];
+        assert!(ticket_ballot_states(&tickets(), &votes, &[]).is_err());
+    }
+}
@@ -3,7 +3,8 @@ use defs::*;
 
 pub fn load_candidate_data(
     candidates: Vec<aec::data::candidates::AECAllCandidateRow>,
-) -> CandidateData {
+) -> Result<CandidateData, String> {
+    check_candidate_count(candidates.len())?;
     let mut names = Vec::new();
     let mut parties = Vec::new();
 
@@ -27,12 +28,70 @@ pub fn load_candidate_data(
         }
 
         let p = tickets.len() - 1;
-        tickets[p].push(CandidateIndex(idx as u8));
+        tickets[p].push(CandidateIndex(idx as u16));
     }
-    CandidateData {
+    Ok(CandidateData {
         count: candidates.len(),
         names,
         parties,
         tickets,
+    })
+}
+
+/// following a double dissolution, determine the senators who are allocated long terms
+/// under the section 282 method: those elected in the recount for half the vacancies who
+/// were also elected in the full count, in their order of election in the recount. Any
+/// remaining long terms go to the other senators in their order of election in the full count.
+pub fn section_282_long_terms(
+    order_of_election: &[CandidateIndex],
+    recount_order_of_election: &[CandidateIndex],
+) -> Vec<CandidateIndex> {
+    let long_terms = order_of_election.len() / 2;
+    let mut allocated: Vec<CandidateIndex> = recount_order_of_election
+        .iter()
+        .filter(|c| order_of_election.contains(c))
+        .take(long_terms)
+        .cloned()
+        .collect();
+    for candidate in order_of_election {
+        if allocated.len() == long_terms {
+            break;
+        }
+        if !allocated.contains(candidate) {
+            allocated.push(*candidate);
+        }
+    }
+    allocated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_terms_from_recount() {
+        let elected: Vec<CandidateIndex> = [0, 5, 1, 6, 2, 7]
+            .iter()
+            .map(|c| CandidateIndex(*c))
+            .collect();
+        let recount = [CandidateIndex(5), CandidateIndex(0), CandidateIndex(1)];
+        assert_eq!(
+            section_282_long_terms(&elected, &recount),
+            vec![CandidateIndex(5), CandidateIndex(0), CandidateIndex(1)]
+        );
+    }
+
+    #[test]
+    fn long_terms_topped_up_by_order_of_election() {
+        let elected: Vec<CandidateIndex> = [0, 5, 1, 6, 2, 7]
+            .iter()
+            .map(|c| CandidateIndex(*c))
+            .collect();
+        // candidate 9 wasn't elected in the full count
+        let recount = [CandidateIndex(6), CandidateIndex(9), CandidateIndex(0)];
+        assert_eq!(
+            section_282_long_terms(&elected, &recount),
+            vec![CandidateIndex(6), CandidateIndex(0), CandidateIndex(5)]
+        );
     }
 }